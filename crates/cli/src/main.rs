@@ -1,42 +1,104 @@
 use std::path::{Path, PathBuf};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use clap::Parser;
-use futures::FutureExt;
 
-use daprox::config::ServerConfig;
+use daprox::config::{LogFormat, ServerConfig};
+use tracing_subscriber::{prelude::*, registry::LookupSpan, EnvFilter, Layer};
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "daprox=trace,info");
     }
-    tracing_subscriber::fmt::init();
 
     let args = Args::parse();
 
-    let config = if let Some(path) = &args.config {
-        load_config_file(&path)
-            .with_context(|| format!("Failed to load config file at '{}'", path.display()))?
-    } else {
-        ServerConfig::from_env().context("Could not construct config")?
-    };
+    let config = load_config(&args)?;
 
+    init_tracing(&config)?;
     tracing::debug!(?config, "loaded config");
+    config.validate()?;
 
-    let shutdown = tokio::signal::ctrl_c().fuse();
+    let server = daprox::server::Server::new(config)?;
 
-    let fut = daprox::server::start(config).fuse();
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(args, server.config_handle()));
 
-    tokio::select! {
-        res = fut => {
-            res
+    server.run_until(shutdown_signal()).await
+}
+
+/// Log to stdout, and serve diagnostics for `tokio-console` if enabled.
+fn init_tracing(config: &ServerConfig) -> Result<(), anyhow::Error> {
+    let filter = match &config.log_level {
+        Some(directives) => EnvFilter::try_new(directives)
+            .with_context(|| format!("Invalid log_level '{directives}'"))?,
+        None => EnvFilter::from_default_env(),
+    };
+    let registry =
+        tracing_subscriber::registry().with(fmt_layer(config.log_format).with_filter(filter));
+
+    #[cfg(feature = "console")]
+    if config.tokio_console {
+        registry.with(console_subscriber::spawn()).init();
+        tracing::info!("serving tokio-console diagnostics");
+        return Ok(());
+    }
+
+    registry.init();
+    #[cfg(not(feature = "console"))]
+    if config.tokio_console {
+        tracing::warn!(
+            "tokio_console is enabled, but daprox was built without the console feature"
+        );
+    }
+    Ok(())
+}
+
+fn fmt_layer<S>(format: LogFormat) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    let layer = tracing_subscriber::fmt::layer();
+    match format {
+        LogFormat::Full => layer.boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// Wait for Ctrl-C, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("Could not install Ctrl-C handler: {}", err);
+            std::future::pending::<()>().await;
         }
-        _ = shutdown => {
-            tracing::info!("shutting down...");
-            Ok(())
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("Could not install SIGTERM handler: {}", err);
+                std::future::pending::<()>().await;
+            }
         }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
     }
+    tracing::info!("shutting down...");
 }
 
 #[derive(clap::Parser, Debug)]
@@ -51,8 +113,147 @@ struct Args {
     config: Option<PathBuf>,
 }
 
+fn load_config(args: &Args) -> Result<ServerConfig, anyhow::Error> {
+    // Environment variables take precedence over the config file.
+    let config = if let Some(path) = &args.config {
+        load_config_file(&path)
+            .with_context(|| format!("Failed to load config file at '{}'", path.display()))?
+    } else {
+        ServerConfig::default()
+    };
+    let mut config = config
+        .merge_env()
+        .context("Could not apply config from env vars")?;
+    config.load_secrets()?;
+    Ok(config)
+}
+
+/// Reload the configuration whenever the process receives SIGHUP.
+#[cfg(unix)]
+async fn reload_on_sighup(args: Args, handle: daprox::server::ConfigHandle) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(err) => {
+            tracing::error!("Could not install SIGHUP handler: {}", err);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        tracing::info!("received SIGHUP, reloading config");
+        let res = load_config(&args).and_then(|config| handle.reload(config));
+        if let Err(err) = res {
+            tracing::error!("Config reload failed, keeping current config: {:#}", err);
+        }
+    }
+}
+
 fn load_config_file(path: &Path) -> Result<ServerConfig, anyhow::Error> {
     let content = std::fs::read_to_string(path)?;
+    let content = interpolate_env(&content)?;
     let conf = serde_yaml::from_str(&content)?;
     Ok(conf)
 }
+
+/// Replace `${VAR}` references with the value of the environment variable.
+///
+/// `${VAR:-default}` falls back to `default` if the variable is unset or
+/// empty. `$$` is a literal `$`, other uses of `$` are kept as is.
+fn interpolate_env(content: &str) -> Result<String, anyhow::Error> {
+    let mut out = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+        let Some(after) = rest.strip_prefix("${") else {
+            out.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let end = after
+            .find('}')
+            .context("Unclosed '${' in config file, use '$$' for a literal '$'")?;
+        let reference = &after[..end];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        let is_valid_name = name
+            .chars()
+            .enumerate()
+            .all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+        if name.is_empty() || !is_valid_name {
+            bail!("Invalid environment variable reference '${{{reference}}}' in config file");
+        }
+
+        match (std::env::var(name), default) {
+            (Ok(value), Some(default)) if value.is_empty() => out.push_str(default),
+            (Ok(value), _) => out.push_str(&value),
+            (Err(std::env::VarError::NotPresent), Some(default)) => out.push_str(default),
+            (Err(std::env::VarError::NotPresent), None) => {
+                bail!("Environment variable '{name}' referenced in config file is not set");
+            }
+            (Err(err), _) => {
+                return Err(err)
+                    .with_context(|| format!("Could not read environment variable '{name}'"));
+            }
+        }
+        rest = &after[end + 1..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate_env() {
+        std::env::set_var("DAPROX_TEST_INTERPOLATE", "value");
+        std::env::set_var("DAPROX_TEST_INTERPOLATE_EMPTY", "");
+        std::env::remove_var("DAPROX_TEST_INTERPOLATE_UNSET");
+
+        assert_eq!(
+            interpolate_env("uri: ${DAPROX_TEST_INTERPOLATE}/db").unwrap(),
+            "uri: value/db"
+        );
+        assert_eq!(
+            interpolate_env("a: ${DAPROX_TEST_INTERPOLATE:-default}").unwrap(),
+            "a: value"
+        );
+        assert_eq!(
+            interpolate_env("a: ${DAPROX_TEST_INTERPOLATE_UNSET:-default}").unwrap(),
+            "a: default"
+        );
+        assert_eq!(
+            interpolate_env("a: ${DAPROX_TEST_INTERPOLATE_EMPTY:-default}").unwrap(),
+            "a: default"
+        );
+        assert_eq!(
+            interpolate_env("a: $$ ${DAPROX_TEST_INTERPOLATE} $x").unwrap(),
+            "a: $ value $x"
+        );
+        assert_eq!(interpolate_env("a: $${x}").unwrap(), "a: ${x}");
+
+        let err = interpolate_env("a: ${DAPROX_TEST_INTERPOLATE_UNSET}").unwrap_err();
+        assert!(err.to_string().contains("is not set"));
+        let err = interpolate_env("a: ${DAPROX_TEST_INTERPOLATE").unwrap_err();
+        assert!(err.to_string().contains("Unclosed '${'"));
+        let err = interpolate_env("a: ${1X}").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Invalid environment variable reference"));
+    }
+}
@@ -0,0 +1,175 @@
+//! Minimal lexing of SQL text.
+//!
+//! Understands just enough syntax to find statement boundaries and keywords:
+//! string literals, quoted identifiers, dollar-quoted strings and comments.
+
+/// Kind of a [`Token`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TokenKind {
+    /// An unquoted keyword or identifier.
+    Word,
+    /// A `;` separating statements.
+    Semicolon,
+    /// A `--` or `/* */` comment.
+    /// Only returned by [`tokens_with_comments`].
+    Comment,
+    /// Anything else, like literals, quoted identifiers and operators.
+    Other,
+}
+
+/// A token of SQL text.
+///
+/// Whitespace and comments are skipped.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+}
+
+/// Split SQL text into tokens.
+pub fn tokens(sql: &str) -> Vec<Token<'_>> {
+    lex(sql, false)
+}
+
+/// Split SQL text into tokens, including comments.
+pub fn tokens_with_comments(sql: &str) -> Vec<Token<'_>> {
+    lex(sql, true)
+}
+
+fn lex(sql: &str, comments: bool) -> Vec<Token<'_>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        let (kind, end) = match (bytes[i], bytes.get(i + 1)) {
+            (b, _) if b.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            (b'-', Some(b'-')) => {
+                let end = match sql[i..].find('\n') {
+                    Some(end) => i + end + 1,
+                    None => bytes.len(),
+                };
+                if !comments {
+                    i = end;
+                    continue;
+                }
+                (TokenKind::Comment, end)
+            }
+            (b'/', Some(b'*')) => {
+                let end = skip_block_comment(bytes, i);
+                if !comments {
+                    i = end;
+                    continue;
+                }
+                (TokenKind::Comment, end)
+            }
+            (b';', _) => (TokenKind::Semicolon, i + 1),
+            (b'E' | b'e', Some(b'\'')) => (TokenKind::Other, skip_quoted(bytes, i + 1, true)),
+            (b'\'', _) => (TokenKind::Other, skip_quoted(bytes, i, false)),
+            (b'"', _) => (TokenKind::Other, skip_quoted(bytes, i, false)),
+            (b'$', _) => (TokenKind::Other, skip_dollar(sql, i)),
+            (b, _) if is_word_start(b) => {
+                let len = bytes[i..].iter().take_while(|b| is_word_byte(**b)).count();
+                (TokenKind::Word, i + len)
+            }
+            _ => (TokenKind::Other, i + 1),
+        };
+        tokens.push(Token {
+            kind,
+            text: &sql[start..end],
+        });
+        i = end;
+    }
+
+    tokens
+}
+
+/// Split SQL text into its top-level statements.
+///
+/// Statements without any tokens, like trailing semicolons or comments, are
+/// omitted.
+pub fn statements(sql: &str) -> Vec<Vec<Token<'_>>> {
+    tokens(sql)
+        .split(|token| token.kind == TokenKind::Semicolon)
+        .filter(|statement| !statement.is_empty())
+        .map(|statement| statement.to_vec())
+        .collect()
+}
+
+/// Non-ASCII bytes are treated as part of words, so tokens never split a
+/// multi-byte character.
+fn is_word_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_' || !b.is_ascii()
+}
+
+fn is_word_byte(b: u8) -> bool {
+    is_word_start(b) || b.is_ascii_digit() || b == b'$'
+}
+
+/// Skip a quoted string or identifier starting at `start`.
+///
+/// Doubled quotes are handled as a closed and reopened quote.
+/// Returns the index after the closing quote.
+fn skip_quoted(bytes: &[u8], start: usize, escapes: bool) -> usize {
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if escapes => i += 2,
+            b if b == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Skip a (possibly nested) block comment starting at `start`.
+fn skip_block_comment(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (b'/', Some(b'*')) => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', Some(b'/')) => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Skip a dollar-quoted string like `$tag$...$tag$` starting at `start`.
+///
+/// Other uses of `$`, like positional parameters, are skipped as a single
+/// character.
+fn skip_dollar(sql: &str, start: usize) -> usize {
+    let rest = &sql.as_bytes()[start + 1..];
+    let tag_len = rest
+        .iter()
+        .take_while(|b| b.is_ascii_alphanumeric() || **b == b'_')
+        .count();
+    let is_tag =
+        rest.get(tag_len) == Some(&b'$') && !matches!(rest.first(), Some(b) if b.is_ascii_digit());
+    if !is_tag {
+        return start + 1;
+    }
+
+    let delimiter = &sql[start..start + tag_len + 2];
+    let body = start + delimiter.len();
+    match sql[body..].find(delimiter) {
+        Some(end) => body + end + delimiter.len(),
+        None => sql.len(),
+    }
+}
@@ -1,15 +1,153 @@
-#![feature(async_fn_in_trait)]
+pub mod lexer;
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
+use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt as _};
 use serde_json::Value as JsonValue;
 
-#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone, Default, Debug)]
 pub struct SqlQuery {
+    #[serde(default)]
     pub query: String,
     pub args: Option<Vec<JsonValue>>,
+    /// Values for named `:name` placeholders in the query.
+    /// Can not be combined with `args`.
     pub kw_args: Option<HashMap<String, JsonValue>>,
     pub db: String,
+    /// Run the query in a transaction and replace `refcursor` columns with
+    /// the rows fetched from the referenced cursors.
+    #[serde(default)]
+    pub fetch_cursors: bool,
+    /// Run the query in a `READ ONLY` transaction, so the database rejects
+    /// any writes.
+    #[serde(default)]
+    pub read_only_tx: bool,
+    /// Convert all timestamp columns to UTC RFC3339 strings with a `Z`
+    /// suffix.
+    #[serde(default)]
+    pub normalize_timestamps_utc: bool,
+    /// Timezone that naive `timestamp` values are assumed to be in when
+    /// normalizing to UTC, as a fixed offset like `+02:00`.
+    /// Defaults to UTC.
+    #[serde(default)]
+    pub assume_timezone: Option<String>,
+    #[serde(default)]
+    pub protocol: QueryProtocol,
+    #[serde(default)]
+    pub bytea_encoding: ByteaEncoding,
+    /// Only supported for Postgres.
+    #[serde(default)]
+    pub json_encoding: JsonEncoding,
+    /// Leave out the keys of SQL `NULL` values in rows returned as maps.
+    /// Rows returned as column arrays keep their nulls.
+    #[serde(default)]
+    pub omit_nulls: bool,
+    /// Return values of column types that have no JSON conversion as
+    /// strings, instead of failing the query.
+    /// Only supported for Postgres.
+    #[serde(default)]
+    pub unsupported_types_as_text: bool,
+    /// Allow the query to contain multiple statements separated by
+    /// semicolons.
+    /// For Postgres, this requires the simple protocol.
+    #[serde(default)]
+    pub allow_multiple: bool,
+    /// Maximum number of rows to return, appended to the query as a `LIMIT`
+    /// clause. Only supported for Postgres.
+    #[serde(default)]
+    pub limit: Option<u64>,
+    /// Number of rows to skip, appended to the query as an `OFFSET` clause.
+    /// Only supported for Postgres.
+    #[serde(default)]
+    pub offset: Option<u64>,
+    /// Maximum number of rows the server returns, from its configuration.
+    /// Can not be set by clients.
+    #[serde(skip)]
+    pub max_rows: Option<usize>,
+    /// Collects the notices the database sends while running the query.
+    /// Only supported for Postgres. Can not be set by clients.
+    #[serde(skip)]
+    pub notices: Option<NoticeSink>,
+}
+
+impl SqlQuery {
+    /// Number of rows backends need to collect at most.
+    ///
+    /// One row more than `max_rows`, so callers can tell whether the result
+    /// was truncated.
+    pub fn collect_limit(&self) -> usize {
+        self.max_rows
+            .map_or(usize::MAX, |max_rows| max_rows.saturating_add(1))
+    }
+
+    /// Check that the query and database are not empty.
+    pub fn validate(&self) -> Result<(), ArgumentError> {
+        if self.query.trim().is_empty() {
+            return Err(ArgumentError("query must not be empty".to_string()));
+        }
+        if self.db.trim().is_empty() {
+            return Err(ArgumentError("db must not be empty".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Check that the query contains a single statement, unless
+    /// `allow_multiple` is set.
+    pub fn check_statement_count(&self) -> Result<(), ArgumentError> {
+        if self.allow_multiple {
+            return Ok(());
+        }
+        let count = lexer::statements(&self.query).len();
+        if count > 1 {
+            return Err(ArgumentError(format!(
+                "query contains {count} statements, but only a single statement is allowed \
+                 unless allow_multiple is set"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Encoding of binary `bytea` values in JSON output.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ByteaEncoding {
+    /// Standard base64 with padding.
+    #[default]
+    Base64,
+    /// Hex digits prefixed with `\x`, like the Postgres text format.
+    Hex,
+}
+
+/// Encoding of `json` and `jsonb` values in JSON output.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonEncoding {
+    /// Embedded as nested JSON values.
+    #[default]
+    Structured,
+    /// Strings with the JSON text, as stored in the database.
+    Text,
+}
+
+/// Wire protocol used to run a query.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryProtocol {
+    /// Prepare the statement and bind the arguments.
+    /// Values are decoded according to their column type.
+    #[default]
+    Extended,
+    /// Send the query text as-is, which saves a round trip.
+    /// Column types are not known, so all values are returned as text.
+    Simple,
+    /// Use the simple protocol for queries without arguments, and the
+    /// extended protocol otherwise.
+    Auto,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
@@ -17,16 +155,183 @@ pub struct SqlQuery {
 pub enum SqlOutputFormat {
     Json,
     JsonLines,
+    MessagePack,
+    MessagePackLines,
     JsonColumns,
     JsonColumnLines,
+    EventStream,
+    JsonTable,
+    Parquet,
+    Csv,
+    Arrow,
 }
 
 pub type ColumnNames = Vec<String>;
 
-pub trait SqlBackend {
+/// A stream of result rows, serialized as JSON.
+pub type JsonRowStream = BoxStream<'static, Result<JsonValue, anyhow::Error>>;
+
+/// A notice or warning sent by the database while running a query, like
+/// the messages of `RAISE NOTICE` in PL/pgSQL.
+#[derive(serde::Serialize, PartialEq, Eq, Clone, Debug)]
+pub struct Notice {
+    /// Like `NOTICE`, `WARNING` or `INFO`.
+    pub severity: String,
+    /// The SQLSTATE code.
+    pub code: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+/// Shared list that backends add the notices of a query to.
+///
+/// Clones refer to the same list.
+#[derive(Clone, Default, Debug)]
+pub struct NoticeSink(Arc<Mutex<Vec<Notice>>>);
+
+impl NoticeSink {
+    pub fn push(&self, notice: Notice) {
+        self.0.lock().unwrap().push(notice);
+    }
+
+    /// Remove and return the collected notices.
+    pub fn take(&self) -> Vec<Notice> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl PartialEq for NoticeSink {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for NoticeSink {}
+
+/// An error reported by the database server.
+///
+/// Backends convert their native errors into this type, so the server can
+/// report them consistently.
+#[derive(serde::Serialize, PartialEq, Eq, Clone, Default, Debug)]
+pub struct DatabaseError {
+    /// SQLSTATE error code.
+    pub code: Option<String>,
+    pub message: String,
+    pub detail: Option<String>,
+    pub hint: Option<String>,
+    /// 1-based character position of the error in the query text.
+    pub position: Option<u32>,
+    #[serde(skip)]
+    pub kind: DatabaseErrorKind,
+}
+
+/// Broad category of a [`DatabaseError`].
+///
+/// Lets the server distinguish client mistakes from server faults.
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+pub enum DatabaseErrorKind {
+    /// The query is invalid, like a syntax error or invalid input data.
+    InvalidQuery,
+    /// A referenced table, column or function does not exist.
+    UndefinedObject,
+    /// The query violates a constraint, like a unique key.
+    ConstraintViolation,
+    /// The user lacks the privileges for the query.
+    PermissionDenied,
+    /// The database rejected the connection credentials.
+    AuthenticationFailed,
+    /// The database can not be reached or does not accept connections.
+    Unavailable,
+    #[default]
+    Other,
+}
+
+impl std::fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "db error: {}", self.message)
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+/// Invalid query arguments supplied by the client.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ArgumentError(pub String);
+
+impl std::fmt::Display for ArgumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ArgumentError {}
+
+/// Backend-neutral type of a result column.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    Bool,
+    Int16,
+    Int32,
+    Int64,
+    Float32,
+    Float64,
+    Text,
+    /// Timestamp without time zone, as an ISO 8601 string.
+    Timestamp,
+    /// Timestamp with time zone, as an RFC 3339 string.
+    TimestampTz,
+    /// Any other value, including arrays and nested JSON.
+    Json,
+}
+
+/// Name and type of a result column.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct ColumnInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_: ColumnType,
+}
+
+/// A database backend that runs queries.
+///
+/// Object-safe, so backends can be selected at runtime.
+#[async_trait]
+pub trait SqlBackend: Send + Sync {
     async fn query_json_maps(&self, query: SqlQuery) -> Result<Vec<JsonValue>, anyhow::Error>;
     async fn query_column_arrays(
         &self,
         query: SqlQuery,
     ) -> Result<(ColumnNames, Vec<Vec<JsonValue>>), anyhow::Error>;
+    /// Like [`Self::query_column_arrays`], but also returns the column types.
+    ///
+    /// The columns are known even if the query returns no rows.
+    async fn query_typed_columns(
+        &self,
+        query: SqlQuery,
+    ) -> Result<(Vec<ColumnInfo>, Vec<Vec<JsonValue>>), anyhow::Error>;
+
+    /// Like [`Self::query_json_maps`], but returns the rows as a stream.
+    ///
+    /// The default implementation fetches all rows before returning.
+    async fn query_json_map_stream(&self, query: SqlQuery) -> Result<JsonRowStream, anyhow::Error> {
+        let rows = self.query_json_maps(query).await?;
+        Ok(futures::stream::iter(rows.into_iter().map(Ok)).boxed())
+    }
+
+    /// Like [`Self::query_column_arrays`], but returns the rows as a stream
+    /// of JSON arrays.
+    ///
+    /// The default implementation fetches all rows before returning.
+    async fn query_column_array_stream(
+        &self,
+        query: SqlQuery,
+    ) -> Result<(ColumnNames, JsonRowStream), anyhow::Error> {
+        let (names, rows) = self.query_column_arrays(query).await?;
+        let rows = rows.into_iter().map(|row| Ok(JsonValue::Array(row)));
+        Ok((names, futures::stream::iter(rows).boxed()))
+    }
 }
@@ -0,0 +1,30 @@
+//! Exposes build information to the `/version` endpoint.
+
+use std::{path::Path, process::Command, time::SystemTime};
+
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=DAPROX_GIT_COMMIT={commit}");
+
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=DAPROX_BUILD_TIMESTAMP={timestamp}");
+
+    // Update the commit when a different one is checked out.
+    let git_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../.git");
+    for path in ["HEAD", "refs"] {
+        let path = git_dir.join(path);
+        if path.exists() {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+}
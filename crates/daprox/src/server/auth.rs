@@ -0,0 +1,70 @@
+//! Bearer token authentication.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse as _, Response},
+};
+
+use super::{ApiError, ClientToken, Ctx};
+
+/// Check the `Authorization: Bearer <token>` header against the configured
+/// tokens, and insert the matching [`ClientToken`] into the request
+/// extensions.
+///
+/// All requests are allowed if no tokens are configured.
+pub(super) async fn authenticate<B>(
+    State(ctx): State<Ctx>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let config = ctx.config.load();
+    if config.auth.tokens.is_empty() {
+        return next.run(req).await;
+    }
+
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let Some(bearer) = bearer else {
+        return unauthorized("Missing bearer token");
+    };
+
+    // Compare all tokens, so the response time doesn't reveal which one
+    // matched.
+    let bearer = bearer.trim().as_bytes();
+    let mut matched = None;
+    for token in &config.auth.tokens {
+        if constant_time_eq(token.token.as_bytes(), bearer) {
+            matched = Some(token);
+        }
+    }
+    let Some(token) = matched else {
+        return unauthorized("Invalid bearer token");
+    };
+
+    req.extensions_mut().insert(ClientToken {
+        id: token.id.clone(),
+        max_concurrent: token.max_concurrent,
+        read_only: token.read_only,
+    });
+    next.run(req).await
+}
+
+fn unauthorized(message: &str) -> Response {
+    let mut res = ApiError::new(StatusCode::UNAUTHORIZED, message.to_string()).into_response();
+    res.headers_mut()
+        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    res
+}
+
+/// Compare two byte strings in time independent of their contents.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,43 @@
+//! Registry of the database backends.
+
+use std::{collections::HashMap, sync::Arc};
+
+use daprox_core::SqlBackend;
+
+/// Database backends, keyed by the URI scheme of the databases they serve.
+#[derive(Clone, Default)]
+pub(super) struct Backends(HashMap<&'static str, Arc<dyn SqlBackend>>);
+
+impl Backends {
+    /// Serve databases with URIs like `<scheme>://...` with `backend`.
+    pub fn register(&mut self, scheme: &'static str, backend: Arc<dyn SqlBackend>) {
+        self.0.insert(scheme, backend);
+    }
+
+    /// The registered URI schemes, in alphabetical order.
+    pub fn schemes(&self) -> Vec<&'static str> {
+        let mut schemes = self.0.keys().copied().collect::<Vec<_>>();
+        schemes.sort_unstable();
+        schemes
+    }
+
+    /// Whether a backend serves databases with the URI scheme of `uri`.
+    pub fn supports(&self, uri: &str) -> bool {
+        uri.split_once(':')
+            .map_or(false, |(scheme, _)| self.0.contains_key(scheme))
+    }
+
+    /// Select the backend for a database URI by its scheme.
+    pub fn get(&self, uri: &str) -> Result<Arc<dyn SqlBackend>, anyhow::Error> {
+        uri.split_once(':')
+            .and_then(|(scheme, _)| self.0.get(scheme))
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Unsupported database type {}", uri))
+    }
+}
+
+impl std::fmt::Debug for Backends {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
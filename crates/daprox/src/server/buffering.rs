@@ -0,0 +1,146 @@
+//! Hybrid response bodies that are buffered when small and streamed when large.
+
+use std::{pin::Pin, time::Duration};
+
+use axum::body::Body;
+use daprox_core::JsonRowStream;
+use futures::{Stream, StreamExt as _};
+use tokio::time::Instant;
+
+/// Coalesced chunks are flushed once they reach this size.
+const FLUSH_CHUNK_BYTES: usize = 64 * 1024;
+
+/// A serialized JSON array.
+pub(super) enum JsonArrayBody {
+    /// The complete array, small enough to be sent with a `Content-Length`.
+    Buffered(Vec<u8>),
+    /// A chunked body, used once the serialized array exceeds the threshold.
+    Streamed(Body),
+}
+
+/// Serialize rows into a JSON array.
+///
+/// Rows are fetched and serialized into a single buffer until it exceeds
+/// `threshold` bytes. The remaining rows are then fetched and serialized
+/// lazily into chunks of roughly `threshold` bytes while the body is sent,
+/// so large results are never held in memory.
+/// Without a threshold, the array is always buffered.
+pub(super) async fn json_array_body(
+    mut rows: JsonRowStream,
+    threshold: Option<usize>,
+) -> Result<JsonArrayBody, anyhow::Error> {
+    let mut buf = vec![b'['];
+
+    let mut first = true;
+    while let Some(row) = rows.next().await {
+        if !first {
+            buf.push(b',');
+        }
+        first = false;
+        serde_json::to_writer(&mut buf, &row?)?;
+
+        if let Some(threshold) = threshold {
+            if buf.len() > threshold {
+                return Ok(JsonArrayBody::Streamed(stream_remaining(
+                    buf, rows, threshold,
+                )));
+            }
+        }
+    }
+
+    buf.push(b']');
+    Ok(JsonArrayBody::Buffered(buf))
+}
+
+/// Build a body that starts with `head` and continues with the serialized
+/// remaining array rows.
+///
+/// Errors while fetching the remaining rows abort the body.
+fn stream_remaining(head: Vec<u8>, rows: JsonRowStream, chunk_size: usize) -> Body {
+    let rest = futures::stream::unfold(Some(rows), move |rows| async move {
+        let mut rows = rows?;
+        let mut buf = Vec::with_capacity(chunk_size);
+        while let Some(row) = rows.next().await {
+            buf.push(b',');
+            let written = row.and_then(|row| Ok(serde_json::to_writer(&mut buf, &row)?));
+            if let Err(err) = written {
+                return Some((Err(err), None));
+            }
+            if buf.len() >= chunk_size {
+                return Some((Ok(buf), Some(rows)));
+            }
+        }
+        buf.push(b']');
+        Some((Ok(buf), None))
+    });
+
+    let head = futures::stream::once(async move { Ok::<_, anyhow::Error>(head) });
+    Body::wrap_stream(head.chain(rest))
+}
+
+/// Coalesce small chunks, like the single rows of line-based formats, into
+/// larger writes.
+///
+/// Pending data is flushed once it reaches [`FLUSH_CHUNK_BYTES`], and at
+/// least every `interval`, so slowly produced rows still reach the client.
+pub(super) fn flush_interval<S>(
+    chunks: S,
+    interval: Duration,
+) -> impl Stream<Item = Result<Vec<u8>, anyhow::Error>> + Send + 'static
+where
+    S: Stream<Item = Result<Vec<u8>, anyhow::Error>> + Send + 'static,
+{
+    let state = Coalesce {
+        chunks: Some(Box::pin(chunks)),
+        buf: Vec::new(),
+        deadline: Instant::now() + interval,
+    };
+    futures::stream::unfold(state, move |mut state| async move {
+        loop {
+            let chunks = state.chunks.as_mut()?;
+            tokio::select! {
+                chunk = chunks.next() => match chunk {
+                    Some(Ok(chunk)) => {
+                        state.buf.extend_from_slice(&chunk);
+                        if state.buf.len() >= FLUSH_CHUNK_BYTES {
+                            return Some((Ok(state.take(interval)), state));
+                        }
+                    }
+                    Some(Err(err)) => {
+                        state.chunks = None;
+                        return Some((Err(err), state));
+                    }
+                    None => {
+                        state.chunks = None;
+                        if state.buf.is_empty() {
+                            return None;
+                        }
+                        return Some((Ok(state.take(interval)), state));
+                    }
+                },
+                _ = tokio::time::sleep_until(state.deadline) => {
+                    if !state.buf.is_empty() {
+                        return Some((Ok(state.take(interval)), state));
+                    }
+                    state.deadline = Instant::now() + interval;
+                }
+            }
+        }
+    })
+}
+
+struct Coalesce<S> {
+    /// `None` once the inner stream ended or failed.
+    chunks: Option<Pin<Box<S>>>,
+    buf: Vec<u8>,
+    /// When pending data is flushed at the latest.
+    deadline: Instant,
+}
+
+impl<S> Coalesce<S> {
+    /// Take the pending data and restart the flush interval.
+    fn take(&mut self, interval: Duration) -> Vec<u8> {
+        self.deadline = Instant::now() + interval;
+        std::mem::take(&mut self.buf)
+    }
+}
@@ -0,0 +1,142 @@
+//! Columnar (Arrow based) output formats.
+
+use std::sync::Arc;
+
+use anyhow::bail;
+use arrow::{
+    array::{
+        ArrayRef, BooleanArray, Float32Array, Float64Array, Int16Array, Int32Array, Int64Array,
+        StringArray, TimestampMicrosecondArray,
+    },
+    datatypes::{DataType, Field, Schema, TimeUnit},
+    ipc::writer::StreamWriter,
+    record_batch::RecordBatch,
+};
+use chrono::{DateTime, NaiveDateTime};
+use daprox_core::{ColumnInfo, ColumnType};
+use parquet::arrow::ArrowWriter;
+use serde_json::Value as JsonValue;
+
+pub(super) const PARQUET_CONTENT_TYPE: &str = "application/vnd.apache.parquet";
+pub(super) const ARROW_STREAM_CONTENT_TYPE: &str = "application/vnd.apache.arrow.stream";
+
+/// Time zone of [`ColumnType::TimestampTz`] columns.
+///
+/// Values are normalized to UTC.
+const UTC: &str = "UTC";
+
+fn arrow_type(ty: ColumnType) -> DataType {
+    match ty {
+        ColumnType::Bool => DataType::Boolean,
+        ColumnType::Int16 => DataType::Int16,
+        ColumnType::Int32 => DataType::Int32,
+        ColumnType::Int64 => DataType::Int64,
+        ColumnType::Float32 => DataType::Float32,
+        ColumnType::Float64 => DataType::Float64,
+        ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, None),
+        ColumnType::TimestampTz => {
+            DataType::Timestamp(TimeUnit::Microsecond, Some(UTC.to_string()))
+        }
+        ColumnType::Text | ColumnType::Json => DataType::Utf8,
+    }
+}
+
+/// Parse a timestamp string into microseconds since the Unix epoch.
+///
+/// Accepts RFC 3339 timestamps with an offset, which are converted to UTC,
+/// and ISO 8601 timestamps without one.
+fn timestamp_micros(value: &str) -> Option<i64> {
+    if let Ok(ts) = DateTime::parse_from_rfc3339(value) {
+        return Some(ts.timestamp_micros());
+    }
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .map(|ts| ts.timestamp_micros())
+}
+
+pub(super) fn arrow_schema(columns: &[ColumnInfo]) -> Schema {
+    let fields = columns
+        .iter()
+        .map(|c| Field::new(&c.name, arrow_type(c.type_), true))
+        .collect::<Vec<_>>();
+    Schema::new(fields)
+}
+
+/// Build an Arrow array from the values of a single column.
+fn column_array<'a>(ty: ColumnType, values: impl Iterator<Item = &'a JsonValue>) -> ArrayRef {
+    match ty {
+        ColumnType::Bool => Arc::new(values.map(|v| v.as_bool()).collect::<BooleanArray>()),
+        ColumnType::Int16 => Arc::new(
+            values
+                .map(|v| v.as_i64().map(|x| x as i16))
+                .collect::<Int16Array>(),
+        ),
+        ColumnType::Int32 => Arc::new(
+            values
+                .map(|v| v.as_i64().map(|x| x as i32))
+                .collect::<Int32Array>(),
+        ),
+        ColumnType::Int64 => Arc::new(values.map(|v| v.as_i64()).collect::<Int64Array>()),
+        ColumnType::Float32 => Arc::new(
+            values
+                .map(|v| v.as_f64().map(|x| x as f32))
+                .collect::<Float32Array>(),
+        ),
+        ColumnType::Float64 => Arc::new(values.map(|v| v.as_f64()).collect::<Float64Array>()),
+        ColumnType::Text => Arc::new(values.map(|v| v.as_str()).collect::<StringArray>()),
+        // Values that can not be parsed, like infinity, are stored as null.
+        ColumnType::Timestamp => Arc::new(
+            values
+                .map(|v| v.as_str().and_then(timestamp_micros))
+                .collect::<TimestampMicrosecondArray>(),
+        ),
+        ColumnType::TimestampTz => Arc::new(
+            values
+                .map(|v| v.as_str().and_then(timestamp_micros))
+                .collect::<TimestampMicrosecondArray>()
+                .with_timezone(UTC.to_string()),
+        ),
+        // Nested values are stored as their JSON text.
+        ColumnType::Json => Arc::new(
+            values
+                .map(|v| match v {
+                    JsonValue::Null => None,
+                    other => Some(other.to_string()),
+                })
+                .collect::<StringArray>(),
+        ),
+    }
+}
+
+pub(super) fn record_batch(
+    columns: &[ColumnInfo],
+    rows: &[Vec<JsonValue>],
+) -> Result<RecordBatch, anyhow::Error> {
+    if columns.is_empty() {
+        bail!("Columnar output formats require at least one result column");
+    }
+
+    let arrays = columns
+        .iter()
+        .enumerate()
+        .map(|(index, col)| column_array(col.type_, rows.iter().map(|r| &r[index])))
+        .collect();
+
+    let batch = RecordBatch::try_new(Arc::new(arrow_schema(columns)), arrays)?;
+    Ok(batch)
+}
+
+pub(super) fn write_parquet(batch: &RecordBatch) -> Result<Vec<u8>, anyhow::Error> {
+    let mut buf = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut buf, batch.schema(), None)?;
+    writer.write(batch)?;
+    writer.close()?;
+    Ok(buf)
+}
+
+pub(super) fn write_arrow_stream(batch: &RecordBatch) -> Result<Vec<u8>, anyhow::Error> {
+    let mut writer = StreamWriter::try_new(Vec::new(), &batch.schema())?;
+    writer.write(batch)?;
+    writer.finish()?;
+    Ok(writer.into_inner()?)
+}
@@ -0,0 +1,70 @@
+//! Cross-origin requests from browser clients.
+
+use anyhow::{bail, Context as _};
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use super::request_id::REQUEST_ID_HEADER;
+use crate::config::CorsConfig;
+
+/// Build the CORS layer for the configuration.
+///
+/// Returns `None` if no origins are allowed, so responses carry no CORS
+/// headers at all.
+pub(super) fn layer(config: &CorsConfig) -> Result<Option<CorsLayer>, anyhow::Error> {
+    let origin = if config.allow_any_origin {
+        if config.allow_credentials {
+            bail!("cors: allow_any_origin can not be combined with allow_credentials");
+        }
+        AllowOrigin::any()
+    } else if config.allowed_origins.is_empty() {
+        return Ok(None);
+    } else {
+        let origins = config
+            .allowed_origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin)
+                    .with_context(|| format!("cors: invalid origin '{origin}'"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+
+    let methods = if config.allowed_methods.is_empty() {
+        vec![Method::GET, Method::POST]
+    } else {
+        config
+            .allowed_methods
+            .iter()
+            .map(|method| {
+                method
+                    .to_uppercase()
+                    .parse::<Method>()
+                    .with_context(|| format!("cors: invalid method '{method}'"))
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    let headers = if config.allowed_headers.is_empty() {
+        vec![header::AUTHORIZATION, header::CONTENT_TYPE]
+    } else {
+        config
+            .allowed_headers
+            .iter()
+            .map(|name| {
+                name.parse::<HeaderName>()
+                    .with_context(|| format!("cors: invalid header '{name}'"))
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(origin)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .expose_headers([HeaderName::from_static(REQUEST_ID_HEADER)])
+            .allow_credentials(config.allow_credentials),
+    ))
+}
@@ -0,0 +1,47 @@
+//! CSV output, as described in RFC 4180.
+
+use serde_json::Value as JsonValue;
+
+pub(super) const CSV_CONTENT_TYPE: &str = "text/csv";
+
+/// Serialize rows to CSV, with the column names as the header row.
+///
+/// NULL values are written as `null_string`. Strings that equal
+/// `null_string` are quoted to tell them apart. Nested values are written as
+/// their JSON text.
+pub(super) fn write_csv(names: &[String], rows: &[Vec<JsonValue>], null_string: &str) -> Vec<u8> {
+    let mut out = String::new();
+    write_record(&mut out, names.iter().map(|name| cell(name, false)));
+    for row in rows {
+        write_record(
+            &mut out,
+            row.iter().map(|value| match value {
+                JsonValue::Null => cell(null_string, false),
+                JsonValue::String(s) => cell(s, s == null_string),
+                other => cell(&other.to_string(), false),
+            }),
+        );
+    }
+    out.into_bytes()
+}
+
+fn write_record(out: &mut String, cells: impl Iterator<Item = String>) {
+    for (index, cell) in cells.enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        out.push_str(&cell);
+    }
+    out.push_str("\r\n");
+}
+
+/// Quote a field if it contains special characters, or if `force_quote` is
+/// set.
+fn cell(value: &str, force_quote: bool) -> String {
+    let needs_quotes = force_quote || value.contains(|c| matches!(c, ',' | '"' | '\n' | '\r'));
+    if needs_quotes {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
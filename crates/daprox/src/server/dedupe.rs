@@ -0,0 +1,143 @@
+//! Deduplication of identical concurrent queries.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody as _},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use daprox_core::{ArgumentError, DatabaseError, SqlQuery};
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt as _,
+};
+
+use super::{
+    sql::{OutputOptions, SqlOutputFormat},
+    ApiError, Ctx, ServerState,
+};
+
+type SharedResult = Result<BufferedResponse, Arc<anyhow::Error>>;
+type SharedQuery = Shared<BoxFuture<'static, SharedResult>>;
+
+/// Queries that are currently executing, keyed by the full request.
+#[derive(Clone, Default)]
+pub(super) struct InflightQueries(Arc<Mutex<HashMap<String, SharedQuery>>>);
+
+impl std::fmt::Debug for InflightQueries {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InflightQueries").finish_non_exhaustive()
+    }
+}
+
+/// A fully buffered response that can be handed to multiple requests.
+#[derive(Clone, Debug)]
+struct BufferedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl BufferedResponse {
+    async fn from_response(res: Response) -> Result<Self, anyhow::Error> {
+        let (parts, mut body) = res.into_parts();
+        let mut buf = Vec::new();
+        while let Some(chunk) = body.data().await {
+            buf.extend_from_slice(&chunk?);
+        }
+
+        Ok(Self {
+            status: parts.status,
+            headers: parts.headers,
+            body: buf.into(),
+        })
+    }
+
+    fn into_response(self) -> Response {
+        let mut res = Response::new(Body::from(self.body));
+        *res.status_mut() = self.status;
+        *res.headers_mut() = self.headers;
+        res.into_response()
+    }
+}
+
+impl ServerState {
+    /// Like [`ServerState::query_sql`], but concurrent identical queries
+    /// share a single execution.
+    pub(super) async fn query_sql_deduplicated(
+        self: Ctx,
+        query: SqlQuery,
+        format: SqlOutputFormat,
+        options: OutputOptions,
+    ) -> Result<Response, anyhow::Error> {
+        let key = format!("{:?}", (&query, &format, &options));
+
+        let fut = {
+            let mut inflight = self.inflight.0.lock().unwrap();
+            if let Some(fut) = inflight.get(&key) {
+                tracing::trace!("joining identical in-flight query");
+                fut.clone()
+            } else {
+                // The query runs in its own task, so it finishes and its
+                // entry is removed even if all requests waiting for it were
+                // dropped.
+                let entry = InflightEntry {
+                    inflight: self.inflight.clone(),
+                    key: key.clone(),
+                };
+                let ctx = self.clone();
+                let task = tokio::spawn(async move {
+                    let _entry = entry;
+                    let res = match ctx.query_sql(query, format, options).await {
+                        Ok(res) => BufferedResponse::from_response(res).await,
+                        Err(err) => Err(err),
+                    };
+                    res.map_err(Arc::new)
+                });
+                let fut = async move {
+                    task.await.unwrap_or_else(|err| {
+                        Err(Arc::new(anyhow::anyhow!("Query task failed: {}", err)))
+                    })
+                }
+                .boxed()
+                .shared();
+                inflight.insert(key, fut.clone());
+                fut
+            }
+        };
+
+        match fut.await {
+            Ok(res) => Ok(res.into_response()),
+            Err(err) => Err(clone_error(&err)),
+        }
+    }
+}
+
+/// Removes a query from the in-flight queries once it finished, even if
+/// it panicked.
+struct InflightEntry {
+    inflight: InflightQueries,
+    key: String,
+}
+
+impl Drop for InflightEntry {
+    fn drop(&mut self) {
+        self.inflight.0.lock().unwrap().remove(&self.key);
+    }
+}
+
+fn clone_error(err: &anyhow::Error) -> anyhow::Error {
+    if let Some(api_err) = err.downcast_ref::<ApiError>() {
+        api_err.clone().into()
+    } else if let Some(db_err) = err.downcast_ref::<DatabaseError>() {
+        db_err.clone().into()
+    } else if let Some(arg_err) = err.downcast_ref::<ArgumentError>() {
+        arg_err.clone().into()
+    } else {
+        anyhow::anyhow!("{:#}", err)
+    }
+}
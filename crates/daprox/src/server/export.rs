@@ -0,0 +1,95 @@
+//! Exporting query results to an object store.
+
+use anyhow::Context as _;
+use axum::body::{BoxBody, HttpBody as _};
+use object_store::{aws::AmazonS3Builder, path::Path as ObjectPath, ObjectStore};
+use tokio::io::AsyncWriteExt as _;
+
+use crate::config::ObjectStoreConfig;
+
+/// Location of an exported result.
+#[derive(serde::Serialize, Clone, Debug)]
+pub(super) struct ExportLocation {
+    pub key: String,
+    pub url: String,
+}
+
+/// A configured object store for exported results.
+#[derive(Debug)]
+pub(super) struct ExportStore {
+    store: Box<dyn ObjectStore>,
+    config: ObjectStoreConfig,
+}
+
+impl ExportStore {
+    pub fn new(config: &ObjectStoreConfig) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            store: build_store(config)?,
+            config: config.clone(),
+        })
+    }
+
+    /// Stream a response body into the object store.
+    pub async fn upload_body(
+        &self,
+        key: &str,
+        body: BoxBody,
+    ) -> Result<ExportLocation, anyhow::Error> {
+        upload_body(self.store.as_ref(), &self.config, key, body).await
+    }
+}
+
+fn build_store(config: &ObjectStoreConfig) -> Result<Box<dyn ObjectStore>, anyhow::Error> {
+    let mut builder = AmazonS3Builder::new().with_bucket_name(&config.bucket);
+    if let Some(region) = &config.region {
+        builder = builder.with_region(region);
+    }
+    if let Some(endpoint) = &config.endpoint {
+        builder = builder.with_endpoint(endpoint).with_allow_http(true);
+    }
+    if let Some(key) = &config.access_key_id {
+        builder = builder.with_access_key_id(key);
+    }
+    if let Some(secret) = &config.secret_access_key {
+        builder = builder.with_secret_access_key(secret);
+    }
+
+    let store = builder
+        .build()
+        .context("Could not configure export object store")?;
+    Ok(Box::new(store))
+}
+
+/// Stream a response body into the object store as a multipart upload.
+async fn upload_body(
+    store: &dyn ObjectStore,
+    config: &ObjectStoreConfig,
+    key: &str,
+    mut body: BoxBody,
+) -> Result<ExportLocation, anyhow::Error> {
+    let key = format!("{}{}", config.prefix, key.trim_start_matches('/'));
+    let path = ObjectPath::parse(&key).context("Invalid export key")?;
+
+    let (upload_id, mut writer) = store.put_multipart(&path).await?;
+    let res = async {
+        while let Some(chunk) = body.data().await {
+            writer.write_all(&chunk?).await?;
+        }
+        writer.shutdown().await?;
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+
+    if let Err(err) = res {
+        if let Err(abort_err) = store.abort_multipart(&path, &upload_id).await {
+            tracing::warn!(%key, "Could not abort failed export upload: {}", abort_err);
+        }
+        return Err(err.context(format!("Failed to export result to '{key}'")));
+    }
+
+    tracing::debug!(%key, "exported query result");
+    Ok(ExportLocation {
+        url: format!("s3://{}/{}", config.bucket, key),
+        key,
+    })
+}
@@ -0,0 +1,82 @@
+//! Liveness and readiness checks.
+
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use daprox_core::SqlQuery;
+use serde_json::json;
+
+use super::{AppState, ServerState};
+
+/// Maximum time a database may take to answer the readiness check.
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Liveness check, which succeeds as long as the server is running.
+pub(super) async fn handler_health() -> Response {
+    Json(json!({ "status": "ok" })).into_response()
+}
+
+/// Readiness check, which runs `SELECT 1` against all configured databases.
+///
+/// Responds with 503 and the aliases of the failed databases if any of them
+/// can not be reached.
+pub(super) async fn handler_health_ready(State(ctx): AppState) -> Response {
+    let config = ctx.config.load_full();
+    let checks = config
+        .databases
+        .iter()
+        .filter_map(|(alias, db)| Some((alias, db.uri.as_ref()?)))
+        .map(|(alias, uri)| {
+            let ctx = &ctx;
+            async move {
+                let res = tokio::time::timeout(READY_TIMEOUT, ctx.ping(uri)).await;
+                match res {
+                    Ok(Ok(())) => None,
+                    Ok(Err(err)) => {
+                        tracing::warn!(%alias, "Readiness check failed: {:#}", err);
+                        Some(alias.clone())
+                    }
+                    Err(_) => {
+                        tracing::warn!(%alias, "Readiness check timed out");
+                        Some(alias.clone())
+                    }
+                }
+            }
+        });
+
+    let mut failed = futures::future::join_all(checks)
+        .await
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    if failed.is_empty() {
+        return Json(json!({ "status": "ok" })).into_response();
+    }
+
+    failed.sort();
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({ "status": "unavailable", "failed": failed })),
+    )
+        .into_response()
+}
+
+impl ServerState {
+    /// Check that a database accepts queries.
+    async fn ping(&self, uri: &str) -> Result<(), anyhow::Error> {
+        let query = SqlQuery {
+            db: uri.to_string(),
+            query: "SELECT 1".to_string(),
+            ..Default::default()
+        };
+
+        let backend = self.backends.load().get(&query.db)?;
+        backend.query_json_maps(query).await?;
+        Ok(())
+    }
+}
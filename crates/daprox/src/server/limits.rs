@@ -0,0 +1,155 @@
+//! Global and per-client concurrency limits.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{body::HttpBody as _, http::StatusCode, response::Response};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::{ApiError, ServerState};
+use crate::config::ReadOnlyMode;
+
+/// Identity of the authenticated client that sent a request.
+///
+/// Inserted into the request extensions by the authentication layer.
+#[derive(Clone, Debug)]
+pub struct ClientToken {
+    /// Stable identifier of the token.
+    pub id: String,
+    /// Maximum number of queries this token may run at the same time.
+    /// Unlimited if `None`.
+    pub max_concurrent: Option<usize>,
+    /// Overrides the global read-only mode.
+    pub read_only: Option<ReadOnlyMode>,
+}
+
+/// Semaphore limiting the number of concurrent queries of all clients.
+#[derive(Default, Debug)]
+pub(super) struct QueryLimit(Mutex<Option<(usize, Arc<Semaphore>)>>);
+
+impl QueryLimit {
+    /// Reserve a query slot.
+    ///
+    /// Returns `None` if there is no limit.
+    /// Fails with 503 if all slots are in use.
+    pub fn acquire(&self, max: Option<usize>) -> Result<Option<OwnedSemaphorePermit>, ApiError> {
+        let Some(max) = max else {
+            return Ok(None);
+        };
+
+        let semaphore = {
+            let mut limit = self.0.lock().unwrap();
+            // Created on first use, or replaced if the limit changed with a
+            // config reload.
+            match &*limit {
+                Some((current, semaphore)) if *current == max => semaphore.clone(),
+                _ => {
+                    let semaphore = Arc::new(Semaphore::new(max));
+                    *limit = Some((max, semaphore.clone()));
+                    semaphore
+                }
+            }
+        };
+
+        semaphore.try_acquire_owned().map(Some).map_err(|_| {
+            ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("Too many concurrent queries, the limit of this server is {max}"),
+            )
+            .with_retry_after(1)
+        })
+    }
+}
+
+/// Semaphores limiting the number of concurrent queries per token.
+#[derive(Default, Debug)]
+pub(super) struct TokenLimits(Mutex<HashMap<String, (usize, Arc<Semaphore>)>>);
+
+impl TokenLimits {
+    /// Reserve a query slot for the given token.
+    ///
+    /// Returns `None` if the token is unlimited.
+    /// Fails with 429 if all of the token's slots are in use.
+    pub fn acquire(&self, token: &ClientToken) -> Result<Option<OwnedSemaphorePermit>, ApiError> {
+        let Some(max) = token.max_concurrent else {
+            return Ok(None);
+        };
+
+        let semaphore = {
+            let mut map = self.0.lock().unwrap();
+            let entry = map
+                .entry(token.id.clone())
+                .or_insert_with(|| (max, Arc::new(Semaphore::new(max))));
+            // The limit changed with a config reload.
+            // Queries holding permits of the old semaphore finish normally.
+            if entry.0 != max {
+                *entry = (max, Arc::new(Semaphore::new(max)));
+            }
+            entry.1.clone()
+        };
+
+        semaphore.try_acquire_owned().map(Some).map_err(|_| {
+            ApiError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("Too many concurrent queries, the limit for this token is {max}"),
+            )
+        })
+    }
+}
+
+/// Concurrency slots of a query, held until its response was sent.
+#[derive(Default, Debug)]
+pub(super) struct QueryPermits(Vec<OwnedSemaphorePermit>);
+
+impl QueryPermits {
+    /// Hold the permits until the body of the response was sent, or the
+    /// client disconnected.
+    pub fn attach(self, res: Response) -> Response {
+        if self.0.is_empty() {
+            return res;
+        }
+        res.map(|body| {
+            axum::body::boxed(body.map_data(move |data| {
+                let _permits = &self;
+                data
+            }))
+        })
+    }
+}
+
+impl ServerState {
+    /// Reserve a slot of the client's token, if it limits concurrent
+    /// queries.
+    pub(super) fn acquire_token_permit(
+        &self,
+        client: Option<&ClientToken>,
+    ) -> Result<QueryPermits, ApiError> {
+        let permit = match client {
+            Some(token) => self.token_limits.acquire(token)?,
+            None => None,
+        };
+        Ok(QueryPermits(permit.into_iter().collect()))
+    }
+
+    /// Reserve a slot of the global query limit.
+    ///
+    /// Must be held by everything that executes SQL, so
+    /// `max_concurrent_queries` bounds the work of all endpoints.
+    pub(super) fn acquire_global_permit(&self) -> Result<QueryPermits, ApiError> {
+        let max = self.config.load().max_concurrent_queries;
+        let permit = self.query_limit.acquire(max)?;
+        Ok(QueryPermits(permit.into_iter().collect()))
+    }
+
+    /// Reserve a slot of the client's token and of the global query limit.
+    pub(super) fn acquire_query_permits(
+        &self,
+        client: Option<&ClientToken>,
+    ) -> Result<QueryPermits, ApiError> {
+        let QueryPermits(mut permits) = self.acquire_token_permit(client)?;
+        permits.extend(self.acquire_global_permit()?.0);
+        Ok(QueryPermits(permits))
+    }
+}
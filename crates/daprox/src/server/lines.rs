@@ -0,0 +1,64 @@
+//! Streamed line-based bodies: newline-delimited JSON and server-sent
+//! events.
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+
+use axum::response::sse::Event;
+
+use daprox_core::JsonRowStream;
+use futures::{Stream, StreamExt as _};
+use serde_json::Value as JsonValue;
+
+/// Serialize rows into chunks of one JSON value per line.
+///
+/// The `header` is written as the first line.
+/// Rows are only fetched from the backend as the chunks are consumed.
+pub(super) fn json_lines(
+    header: Option<JsonValue>,
+    rows: JsonRowStream,
+) -> impl Stream<Item = Result<Vec<u8>, anyhow::Error>> + Send + 'static {
+    futures::stream::iter(header.map(Ok))
+        .chain(rows)
+        .map(|value| {
+            let mut buf = row_text(value)?.into_bytes();
+            buf.push(b'\n');
+            Ok(buf)
+        })
+}
+
+/// Serialize rows into server-sent events with one JSON value each.
+///
+/// The rows are followed by a `complete` event with the number of rows,
+/// and whether rows were omitted because of the row limit.
+///
+/// `truncated` is read once the rows ended.
+pub(super) fn row_events(
+    rows: JsonRowStream,
+    truncated: Arc<AtomicBool>,
+) -> impl Stream<Item = Result<Event, anyhow::Error>> + Send + 'static {
+    let count = Arc::new(AtomicUsize::new(0));
+    let complete = {
+        let count = count.clone();
+        futures::stream::once(futures::future::lazy(move |_| {
+            let data = serde_json::json!({
+                "rows": count.load(Ordering::Relaxed),
+                "truncated": truncated.load(Ordering::Relaxed),
+            });
+            Ok(Event::default().event("complete").data(data.to_string()))
+        }))
+    };
+    rows.map(move |value| {
+        let data = row_text(value)?;
+        count.fetch_add(1, Ordering::Relaxed);
+        Ok(Event::default().data(data))
+    })
+    .chain(complete)
+}
+
+/// Serialize a single row of a line-based format.
+fn row_text(value: Result<JsonValue, anyhow::Error>) -> Result<String, anyhow::Error> {
+    Ok(serde_json::to_string(&value?)?)
+}
@@ -0,0 +1,147 @@
+//! Streaming Postgres notifications as Server-Sent Events.
+//!
+//! `GET /sql/listen?db=<db>&channel=<channel>` listens on the channel with a
+//! dedicated connection, and sends the payload of each notification as a
+//! `message` event. The connection is closed once the client disconnects.
+//!
+//! Lost connections are re-established with exponential backoff, followed by
+//! a `reconnected` event, since notifications may have been missed.
+//! Clients that fall too far behind get an `error` event, and the stream
+//! ends.
+
+use std::convert::Infallible;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse as _, Response,
+    },
+    Extension,
+};
+use daprox_postgres::{NotificationOverflow, NotificationStream};
+use futures::StreamExt as _;
+use tokio::sync::OwnedSemaphorePermit;
+
+use super::{
+    unsupported_database, ApiError, AppState, ClientToken, Ctx, HandlerError, HttpApiError,
+};
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub(super) struct ListenParams {
+    pub db: String,
+    pub channel: String,
+}
+
+pub(super) async fn handler_sql_listen(
+    State(ctx): AppState,
+    client: Option<Extension<ClientToken>>,
+    Query(params): Query<ListenParams>,
+) -> Result<Response, HandlerError> {
+    let verbosity = ctx.config.load().error_verbosity;
+    listen(&ctx, client.as_deref(), params)
+        .await
+        .map_err(|err| HandlerError::with_verbosity(err, verbosity, None))
+}
+
+async fn listen(
+    ctx: &Ctx,
+    client: Option<&ClientToken>,
+    mut params: ListenParams,
+) -> Result<Response, anyhow::Error> {
+    let permit = match client {
+        Some(token) => ctx.token_limits.acquire(token)?,
+        None => None,
+    };
+    ctx.resolve_database_unrestricted(&mut params.db)?;
+    ctx.check_database(&params.db)?;
+    if !params.db.starts_with("postgres://") {
+        return Err(unsupported_database(&params.db, &["postgres"]).into());
+    }
+    let notifications = ctx
+        .postgres
+        .load_full()
+        .listen(&params.db, &params.channel)
+        .await?;
+
+    let state = ListenState {
+        ctx: ctx.clone(),
+        db: params.db,
+        channel: params.channel,
+        notifications: Some(notifications),
+        _permit: permit,
+    };
+    let events = futures::stream::unfold(state, |mut state| async move {
+        let notifications = state.notifications.as_mut()?;
+        let event = match notifications.next().await? {
+            Ok(notification) => Event::default().data(notification.payload),
+            // Reconnecting would hide that notifications were dropped.
+            Err(err) if err.is::<NotificationOverflow>() => {
+                tracing::warn!(channel = %state.channel, "Ending LISTEN: {}", err);
+                state.notifications = None;
+                error_event(ApiError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    err.to_string(),
+                ))
+            }
+            Err(err) => state.reconnect(err).await,
+        };
+        Some((Ok::<_, Infallible>(event), state))
+    });
+    // Proxies tend to close idle connections.
+    Ok(Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
+
+/// A notification stream that reconnects if its connection is lost.
+struct ListenState {
+    ctx: Ctx,
+    db: String,
+    channel: String,
+    /// `None` once reconnecting was given up, which ends the stream.
+    notifications: Option<NotificationStream>,
+    /// Held until the client disconnects.
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ListenState {
+    /// Listen on a new connection, with the backoff of
+    /// [`ServerConfig::listen_reconnect`](crate::config::ServerConfig::listen_reconnect).
+    ///
+    /// Returns a `reconnected` event with the number of attempts, since
+    /// notifications sent in the meantime were missed.
+    /// Once all attempts failed, returns an `error` event and ends the stream.
+    async fn reconnect(&mut self, mut err: anyhow::Error) -> Event {
+        self.notifications = None;
+        let config = self.ctx.config.load_full();
+        let mut attempt = 0;
+        while let Some(delay) = config.listen_reconnect.backoff(attempt) {
+            tracing::warn!(channel = %self.channel, "Reconnecting LISTEN in {:?}: {:#}", delay, err);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+            // The backend is replaced if the Postgres settings are reloaded.
+            let postgres = self.ctx.postgres.load_full();
+            match postgres.listen(&self.db, &self.channel).await {
+                Ok(notifications) => {
+                    self.notifications = Some(notifications);
+                    let data = serde_json::json!({ "attempts": attempt });
+                    return Event::default().event("reconnected").data(data.to_string());
+                }
+                Err(e) => err = e,
+            }
+        }
+
+        tracing::warn!(channel = %self.channel, "Giving up reconnecting LISTEN: {:#}", err);
+        error_event(ApiError::from_error(err, config.error_verbosity, None))
+    }
+}
+
+/// An `error` event, which is the last event of the stream.
+fn error_event(error: ApiError) -> Event {
+    Event::default()
+        .event("error")
+        .json_data(HttpApiError::from(error))
+        .unwrap()
+}
@@ -0,0 +1,127 @@
+//! Logging of executed queries.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use axum::http::StatusCode;
+use daprox_core::{JsonRowStream, SqlQuery};
+use futures::StreamExt as _;
+use tracing::Span;
+
+use super::{
+    metrics,
+    sql::{OutputOptions, SqlOutputFormat},
+};
+
+/// Maximum number of characters of the SQL text that is logged.
+const MAX_LOGGED_QUERY_CHARS: usize = 1000;
+
+/// Records the outcome of a single query execution in a tracing span.
+///
+/// Only the first outcome is recorded, clones share it.
+#[derive(Clone, Debug)]
+pub(super) struct QueryLog {
+    span: Span,
+    start: Instant,
+    recorded: Arc<AtomicBool>,
+}
+
+impl QueryLog {
+    /// Start logging a query.
+    ///
+    /// The SQL text is only included with `log_sql`, since it may contain
+    /// personal data.
+    pub fn start(
+        query: &SqlQuery,
+        format: &SqlOutputFormat,
+        options: &OutputOptions,
+        log_sql: bool,
+    ) -> Self {
+        let span = tracing::info_span!(
+            "sql_query",
+            db = options.db_alias.as_deref().unwrap_or("<uri>"),
+            ?format,
+            sql = tracing::field::Empty,
+        );
+        if log_sql {
+            span.record("sql", truncate(&query.query).as_str());
+        }
+        Self {
+            span,
+            start: Instant::now(),
+            recorded: Default::default(),
+        }
+    }
+
+    /// Returns `false` if an outcome was already recorded.
+    fn record(&self) -> bool {
+        !self.recorded.swap(true, Ordering::Relaxed)
+    }
+
+    pub fn finish(&self, rows: usize) {
+        if !self.record() {
+            return;
+        }
+        let elapsed = self.start.elapsed();
+        let elapsed_ms = elapsed.as_millis() as u64;
+        self.span
+            .in_scope(|| tracing::info!(rows, elapsed_ms, "query finished"));
+        metrics::record_query(elapsed, None);
+    }
+
+    /// Log a failed query, with the status of the error response.
+    pub fn fail(&self, err: &dyn std::fmt::Display, status: StatusCode) {
+        if !self.record() {
+            return;
+        }
+        let elapsed = self.start.elapsed();
+        let elapsed_ms = elapsed.as_millis() as u64;
+        self.span
+            .in_scope(|| tracing::warn!(elapsed_ms, "query failed: {:#}", err));
+        metrics::record_query(elapsed, Some(status));
+    }
+
+    /// Count the rows of a stream, and log once it is finished or dropped.
+    pub fn stream(self, rows: JsonRowStream) -> JsonRowStream {
+        let mut guard = StreamGuard {
+            log: self,
+            rows: 0,
+            error: None,
+        };
+        rows.inspect(move |row| match row {
+            Ok(_) => guard.rows += 1,
+            Err(err) => guard.error = Some(format!("{err:#}")),
+        })
+        .boxed()
+    }
+}
+
+/// Logs the outcome of a streamed query when the stream is dropped.
+struct StreamGuard {
+    log: QueryLog,
+    rows: usize,
+    error: Option<String>,
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        match &self.error {
+            // The response status was already sent, so errors while
+            // streaming count as server errors.
+            Some(err) => self.log.fail(err, StatusCode::INTERNAL_SERVER_ERROR),
+            None => self.log.finish(self.rows),
+        }
+    }
+}
+
+fn truncate(sql: &str) -> String {
+    match sql.char_indices().nth(MAX_LOGGED_QUERY_CHARS) {
+        Some((index, _)) => format!("{}...", &sql[..index]),
+        None => sql.to_string(),
+    }
+}
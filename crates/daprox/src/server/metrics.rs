@@ -0,0 +1,90 @@
+//! Prometheus metrics.
+
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use once_cell::sync::OnceCell;
+
+use super::{ApiError, AppState};
+
+const QUERIES_TOTAL: &str = "daprox_queries_total";
+const QUERY_ERRORS_TOTAL: &str = "daprox_query_errors_total";
+const QUERY_DURATION_SECONDS: &str = "daprox_query_duration_seconds";
+
+/// Histogram buckets for query durations, in seconds.
+const DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// The recorder is global, so it is shared by all servers in the process.
+static RECORDER: OnceCell<Option<PrometheusHandle>> = OnceCell::new();
+
+/// Install the Prometheus recorder, if it is not installed yet.
+///
+/// Returns `None` if a different recorder was installed by the application.
+pub(super) fn install() -> Option<&'static PrometheusHandle> {
+    RECORDER
+        .get_or_init(|| {
+            let res = PrometheusBuilder::new()
+                .set_buckets_for_metric(
+                    Matcher::Full(QUERY_DURATION_SECONDS.to_string()),
+                    DURATION_BUCKETS,
+                )
+                .and_then(|builder| builder.install_recorder());
+            match res {
+                Ok(handle) => Some(handle),
+                Err(err) => {
+                    tracing::warn!("Could not install metrics recorder: {}", err);
+                    None
+                }
+            }
+        })
+        .as_ref()
+}
+
+/// Record a finished query.
+///
+/// Failed queries are counted by the status class of the error response,
+/// like `4xx`.
+pub(super) fn record_query(duration: Duration, error_status: Option<StatusCode>) {
+    ::metrics::increment_counter!(QUERIES_TOTAL);
+    ::metrics::histogram!(QUERY_DURATION_SECONDS, duration.as_secs_f64());
+    if let Some(status) = error_status {
+        let class = format!("{}xx", status.as_u16() / 100);
+        ::metrics::increment_counter!(QUERY_ERRORS_TOTAL, "status_class" => class);
+    }
+}
+
+/// Metrics in the Prometheus text format.
+///
+/// Only available if [`crate::config::ServerConfig::metrics_enabled`] is set.
+pub(super) async fn handler_metrics(State(ctx): AppState) -> Response {
+    if !ctx.config.load().metrics_enabled {
+        return ApiError::new(
+            StatusCode::NOT_FOUND,
+            "Metrics are not enabled on this server".to_string(),
+        )
+        .into_response();
+    }
+
+    match install() {
+        Some(handle) => (
+            [(
+                axum::http::header::CONTENT_TYPE,
+                "text/plain; version=0.0.4",
+            )],
+            handle.render(),
+        )
+            .into_response(),
+        None => ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Metrics recorder is not available".to_string(),
+        )
+        .into_response(),
+    }
+}
@@ -1,34 +1,106 @@
+mod auth;
+mod backends;
+mod buffering;
+mod columnar;
+mod cors;
+mod csv;
+mod dedupe;
+mod export;
+mod extract;
+mod health;
+mod limits;
+mod lines;
+mod listen;
+mod logging;
+mod metrics;
+mod msgpack;
+mod negotiate;
+mod policy;
+mod rate_limit;
+mod request_id;
+mod row_limit;
 mod sql;
+#[cfg(unix)]
+mod unix;
+mod version;
+mod websocket;
 
-use std::sync::Arc;
+use std::{future::Future, net::SocketAddr, pin::Pin, sync::Arc, time::Duration};
 
 use anyhow::{bail, Context as _};
+use arc_swap::{ArcSwap, ArcSwapOption};
 use axum::{
-    body::Body,
-    extract::State,
+    body::{Body, Bytes, HttpBody as _},
+    extract::{BodyStream, DefaultBodyLimit, State},
     http::StatusCode,
-    response::{IntoResponse, Response},
-    routing::get,
+    response::{sse::Sse, IntoResponse, Response},
+    routing::{get, post},
     Json, Router,
 };
-use daprox_core::{SqlBackend, SqlQuery};
-use daprox_postgres::PostgresProx;
+use axum_server::tls_rustls::RustlsConfig;
+use daprox_core::{ArgumentError, DatabaseError, DatabaseErrorKind, Notice, SqlBackend, SqlQuery};
+use daprox_mysql::MysqlProx;
+use daprox_postgres::{CopyFormat, PostgresProx};
+use daprox_sqlite::SqliteProx;
+use futures::{FutureExt as _, StreamExt, TryFutureExt as _, TryStreamExt as _};
+use http_body::Limited;
 use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+use tower_http::{
+    compression::{
+        predicate::{NotForContentType, Predicate as _, SizeAbove},
+        CompressionLayer,
+    },
+    cors::CorsLayer,
+    limit::RequestBodyLimitLayer,
+};
+
+use crate::config::{ErrorVerbosity, ListenAddr, ReadOnlyMode, ServerConfig, TlsConfig};
 
-use crate::config::ServerConfig;
+pub use self::limits::ClientToken;
 
-use self::sql::SqlOutputFormat;
+use self::{
+    backends::Backends,
+    buffering::JsonArrayBody,
+    dedupe::InflightQueries,
+    export::ExportStore,
+    limits::{QueryLimit, QueryPermits, TokenLimits},
+    logging::QueryLog,
+    rate_limit::RateLimiter,
+    row_limit::{RowLimit, TRUNCATED_HEADER},
+    sql::{DescribeFormat, OutputOptions, SqlOutputFormat},
+};
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 struct ServerState {
-    config: ServerConfig,
+    /// The current configuration.
+    /// Swapped out on config reloads.
+    config: ArcSwap<ServerConfig>,
+    /// Destination for exported query results.
+    /// Only set if [`ServerConfig::export`] is configured.
+    export_store: ArcSwapOption<ExportStore>,
+    /// The Postgres backend, which holds the connection pools.
+    /// Replaced when the Postgres configuration changes.
+    postgres: ArcSwap<PostgresProx>,
+    mysql: Arc<MysqlProx>,
+    sqlite: Arc<SqliteProx>,
+    /// All backends by URI scheme.
+    /// Rebuilt when the Postgres backend is replaced.
+    backends: ArcSwap<Backends>,
+    inflight: InflightQueries,
+    /// Limit of concurrent queries across all databases.
+    query_limit: QueryLimit,
+    /// Concurrency limits of client tokens.
+    token_limits: TokenLimits,
+    rate_limiter: RateLimiter,
+    /// Built once on startup, since the router can not be changed while the
+    /// server is running.
+    cors: Option<CorsLayer>,
 }
 
 impl Default for ServerState {
     fn default() -> Self {
-        Self {
-            config: Default::default(),
-        }
+        Self::new(Default::default()).unwrap()
     }
 }
 
@@ -36,98 +108,1032 @@ type Ctx = Arc<ServerState>;
 type AppState = State<Ctx>;
 
 impl ServerState {
+    fn new(config: ServerConfig) -> Result<Self, anyhow::Error> {
+        let export_store = config.export.as_ref().map(ExportStore::new).transpose()?;
+        let postgres = Arc::new(PostgresProx::new(config.postgres_config()));
+        let mysql = Arc::new(MysqlProx::new());
+        let sqlite = Arc::new(SqliteProx::new());
+        let backends = register_backends(&postgres, &mysql, &sqlite);
+        let cors = cors::layer(&config.cors)?;
+        if config.metrics_enabled {
+            metrics::install();
+        }
+        Ok(Self {
+            config: ArcSwap::from_pointee(config),
+            export_store: ArcSwapOption::from_pointee(export_store),
+            postgres: ArcSwap::new(postgres),
+            mysql,
+            sqlite,
+            backends: ArcSwap::from_pointee(backends),
+            inflight: Default::default(),
+            query_limit: Default::default(),
+            token_limits: Default::default(),
+            rate_limiter: Default::default(),
+            cors,
+        })
+    }
+
+    /// Close the connection pools of all backends.
+    async fn close(&self) {
+        self.postgres.load().close().await;
+        self.mysql.close().await;
+    }
+
+    /// Apply a new configuration to the running server.
+    ///
+    /// If the new configuration is invalid, the current one is kept.
+    fn reload_config(&self, mut config: ServerConfig) -> Result<(), anyhow::Error> {
+        let current = self.config.load();
+        if config.listen != current.listen {
+            tracing::warn!(
+                listen=%config.listen,
+                "Changing the listen address requires a restart, ignoring"
+            );
+            config.listen = current.listen.clone();
+        }
+        if config.admin_listen != current.admin_listen {
+            tracing::warn!("Changing the admin listen address requires a restart, ignoring");
+            config.admin_listen = current.admin_listen.clone();
+        }
+        // Applied when the router and listeners are built.
+        keep_restart_only(
+            "max_body_size",
+            &mut config.max_body_size,
+            &current.max_body_size,
+        );
+        keep_restart_only("cors", &mut config.cors, &current.cors);
+        keep_restart_only("compression", &mut config.compression, &current.compression);
+        keep_restart_only("tls", &mut config.tls, &current.tls);
+        // Applied when logging is set up.
+        keep_restart_only("log_format", &mut config.log_format, &current.log_format);
+        keep_restart_only("log_level", &mut config.log_level, &current.log_level);
+        keep_restart_only(
+            "tokio_console",
+            &mut config.tokio_console,
+            &current.tokio_console,
+        );
+        config.validate_reload()?;
+
+        let export_store = config
+            .export
+            .as_ref()
+            .map(ExportStore::new)
+            .transpose()
+            .context("Invalid export configuration")?;
+
+        // Pools of removed databases are closed, so requests waiting for a
+        // connection fail instead of using a database that is gone.
+        let removed = current
+            .databases
+            .values()
+            .filter_map(|db| db.uri.clone())
+            .filter(|uri| {
+                !config
+                    .databases
+                    .values()
+                    .any(|db| db.uri.as_ref() == Some(uri))
+            })
+            .collect::<Vec<_>>();
+        let previous_postgres = self.postgres.load_full();
+
+        let postgres_config = config.postgres_config();
+        if postgres_config != current.postgres_config() {
+            // Connections of the previous backend are closed once running
+            // queries have finished.
+            let postgres = Arc::new(PostgresProx::new(postgres_config));
+            let backends = register_backends(&postgres, &self.mysql, &self.sqlite);
+            self.postgres.store(postgres);
+            self.backends.store(Arc::new(backends));
+        }
+
+        if config.metrics_enabled {
+            metrics::install();
+        }
+
+        self.export_store.store(export_store.map(Arc::new));
+        self.config.store(Arc::new(config));
+        // After the new config is active, so failing requests see that their
+        // database alias was removed.
+        if !removed.is_empty() {
+            tracing::info!(
+                "Closing connection pools of {} removed databases",
+                removed.len()
+            );
+            let mysql = self.mysql.clone();
+            tokio::spawn(async move {
+                for uri in removed {
+                    previous_postgres.close_pool(&uri).await;
+                    mysql.close_pool(&uri).await;
+                }
+            });
+        }
+        tracing::info!("Reloaded configuration");
+        Ok(())
+    }
+
+    /// Resolve a database alias and stored query to the connection URI and
+    /// SQL to run.
+    ///
+    /// Enforces `stored_queries_only` of the targeted database.
+    fn resolve_query(
+        &self,
+        query: &mut SqlQuery,
+        stored_query: Option<&str>,
+    ) -> Result<(), ApiError> {
+        let config = self.config.load();
+        let alias = query.db.clone();
+        let database = config.databases.get(&alias);
+
+        match (stored_query, database) {
+            (Some(name), Some(database)) => {
+                if !query.query.is_empty() {
+                    return Err(ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        "Only one of query and stored_query may be set".to_string(),
+                    ));
+                }
+                let Some(sql) = database.queries.get(name) else {
+                    return Err(ApiError::new(
+                        StatusCode::NOT_FOUND,
+                        format!("Unknown stored query '{name}' for database '{alias}'"),
+                    ));
+                };
+                query.query = sql.clone();
+            }
+            (Some(_), None) => {
+                return Err(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    "Stored queries require a configured database alias".to_string(),
+                ));
+            }
+            (None, Some(database)) if database.stored_queries_only => {
+                return Err(stored_queries_only(&alias));
+            }
+            (None, _) => {}
+        }
+
+        self.resolve_database(&mut query.db)
+    }
+
+    /// Like [`Self::resolve_database`], for requests that access a database
+    /// without a query, like `COPY FROM` or reading the schema.
+    ///
+    /// Databases with `stored_queries_only` reject these requests.
+    fn resolve_database_unrestricted(&self, db: &mut String) -> Result<(), ApiError> {
+        let config = self.config.load();
+        if let Some(database) = config.databases.get(db.as_str()) {
+            if database.stored_queries_only {
+                return Err(stored_queries_only(db));
+            }
+        }
+        self.resolve_database(db)
+    }
+
+    /// Resolve a database alias to its connection URI.
+    ///
+    /// Raw connection URIs are only accepted if
+    /// [`ServerConfig::allow_raw_uris`] is set.
+    fn resolve_database(&self, db: &mut String) -> Result<(), ApiError> {
+        let config = self.config.load();
+        if let Some(database) = config.databases.get(db.as_str()) {
+            if let Some(uri) = &database.uri {
+                *db = uri.clone();
+            }
+            return Ok(());
+        }
+
+        if is_connection_uri(db) && !config.allow_raw_uris {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "Connection URIs are not allowed, use a configured database alias".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that the database targeted by a query exists.
+    ///
+    /// Anything that isn't a connection URI is treated as a database alias.
+    fn check_database(&self, db: &str) -> Result<(), ApiError> {
+        if !is_connection_uri(db) {
+            return Err(unknown_alias(db));
+        }
+        let backends = self.backends.load();
+        if !backends.supports(db) {
+            return Err(unsupported_database(db, &backends.schemes()));
+        }
+        Ok(())
+    }
+
     async fn query_sql(
         &self,
-        query: SqlQuery,
+        mut query: SqlQuery,
         format: sql::SqlOutputFormat,
+        options: OutputOptions,
     ) -> Result<Response, anyhow::Error> {
+        self.check_database(&query.db)?;
+        let export_to = options.export_to.clone();
+        let db_alias = options.db_alias.clone();
+        let config = self.config.load_full();
+        let log = QueryLog::start(&query, &format, &options, config.log_queries);
+        query.max_rows = config.max_rows;
+        let limit = RowLimit::new(config.max_rows);
+
+        // Acquired before any backend is involved, and held until the
+        // response was sent.
+        let mut permits = QueryPermits::default();
+        let backend = self.backends.load().get(&query.db).and_then(|backend| {
+            permits = self.acquire_global_permit()?;
+            Ok(backend)
+        });
+        let res = match backend {
+            Ok(backend) => {
+                let run =
+                    Self::query_sql_with_backend(&*backend, query, format, options, &log, &limit);
+                tokio::pin!(run);
+                match config.query_timeout_ms {
+                    Some(ms) => {
+                        match tokio::time::timeout(Duration::from_millis(ms), &mut run).await {
+                            Ok(res) => res,
+                            Err(_) => {
+                                let err = ApiError::new(
+                                    StatusCode::GATEWAY_TIMEOUT,
+                                    format!("Query timed out after {ms}ms"),
+                                );
+                                // Logged before the query future is dropped,
+                                // which would log the rows read so far.
+                                log.fail(&err.message, err.status);
+                                // Dropping the query future cancels the query in
+                                // the database, like when the client disconnects.
+                                drop(run);
+                                Err(err.into())
+                            }
+                        }
+                    }
+                    None => run.await,
+                }
+            }
+            Err(err) => Err(err),
+        };
+        let res = res.map_err(|err| {
+            // The alias may have been removed by a config reload while the
+            // query was running, which also closes its connection pool.
+            let err =
+                match db_alias.filter(|alias| !self.config.load().databases.contains_key(alias)) {
+                    Some(alias) => unknown_alias(&alias).into(),
+                    None => err,
+                };
+            log.fail(&err, error_status(&err));
+            err
+        })?;
+
+        let res = match export_to {
+            Some(key) => self.export_response(&key, res).await?,
+            None => res,
+        };
+        let mut res = permits.attach(res);
+        if limit.truncated() {
+            res.headers_mut().insert(
+                TRUNCATED_HEADER,
+                axum::http::HeaderValue::from_static("true"),
+            );
+        }
+        Ok(res)
+    }
+
+    async fn describe_sql(
+        &self,
+        query: SqlQuery,
+        format: DescribeFormat,
+        interface_name: &str,
+    ) -> Result<Response, anyhow::Error> {
+        self.check_database(&query.db)?;
         if query.db.starts_with("postgres://") {
-            let b = PostgresProx::new();
-            Self::query_sql_with_backend(&b, query, format).await
+            let b = self.postgres.load_full();
+            let columns = b.describe(&query).await?;
+            match format {
+                DescribeFormat::Json => Ok(Json(columns).into_response()),
+                DescribeFormat::TsSchema => {
+                    let ts = daprox_postgres::typescript_interface(interface_name, &columns);
+                    Ok((
+                        [(axum::http::header::CONTENT_TYPE, "application/typescript")],
+                        ts,
+                    )
+                        .into_response())
+                }
+            }
         } else {
-            bail!("Unsupported database type {}", query.db);
+            Err(unsupported_database(&query.db, &["postgres"]).into())
         }
     }
 
-    async fn query_sql_with_backend<B: SqlBackend>(
-        backend: &B,
+    async fn validate_sql(&self, query: SqlQuery) -> Result<Response, anyhow::Error> {
+        self.check_database(&query.db)?;
+        if query.db.starts_with("postgres://") {
+            let b = self.postgres.load_full();
+            let signature = b.validate(&query).await?;
+            Ok(Json(signature).into_response())
+        } else {
+            Err(unsupported_database(&query.db, &["postgres"]).into())
+        }
+    }
+
+    async fn explain_sql(&self, query: SqlQuery, analyze: bool) -> Result<Response, anyhow::Error> {
+        self.check_database(&query.db)?;
+        if query.db.starts_with("postgres://") {
+            let b = self.postgres.load_full();
+            let plan = b.explain(&query, analyze).await?;
+            Ok(Json(plan).into_response())
+        } else {
+            Err(unsupported_database(&query.db, &["postgres"]).into())
+        }
+    }
+
+    async fn schema_sql(&self, mut params: sql::SchemaParams) -> Result<Response, anyhow::Error> {
+        self.resolve_database_unrestricted(&mut params.db)?;
+        self.check_database(&params.db)?;
+        if params.db.starts_with("postgres://") {
+            let b = self.postgres.load_full();
+            let tables = b
+                .schema(
+                    &params.db,
+                    params.schema.as_deref(),
+                    params.table.as_deref(),
+                )
+                .await?;
+            Ok(Json(tables).into_response())
+        } else {
+            Err(unsupported_database(&params.db, &["postgres"]).into())
+        }
+    }
+
+    async fn copy_out_sql(
+        &self,
+        query: SqlQuery,
+        format: CopyFormat,
+        header: bool,
+        client: Option<&ClientToken>,
+    ) -> Result<Response, anyhow::Error> {
+        self.check_database(&query.db)?;
+        if !query.db.starts_with("postgres://") {
+            return Err(unsupported_database(&query.db, &["postgres"]).into());
+        }
+        // Dumps can run for a long time, so they count towards the limits
+        // until the whole body was sent.
+        let permits = self.acquire_query_permits(client)?;
+        let b = self.postgres.load_full();
+        let stream = b.copy_out(&query, format, header).await?;
+        let res = (
+            [(axum::http::header::CONTENT_TYPE, format.content_type())],
+            Body::wrap_stream(stream),
+        )
+            .into_response();
+        Ok(permits.attach(res))
+    }
+
+    async fn copy_in_sql(
+        &self,
+        mut params: sql::CopyInParams,
+        body: BodyStream,
+        client: Option<&ClientToken>,
+    ) -> Result<Response, anyhow::Error> {
+        let config = self.config.load_full();
+        if !config.allow_copy_in {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "COPY FROM is not enabled on this server".to_string(),
+            )
+            .into());
+        }
+        if self.read_only_mode(client) != ReadOnlyMode::Off {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "COPY FROM is not allowed in read-only mode".to_string(),
+            )
+            .into());
+        }
+        self.resolve_database_unrestricted(&mut params.db)?;
+        self.check_database(&params.db)?;
+        // Held until all rows were loaded.
+        let _permits = self.acquire_query_permits(client)?;
+
+        let max_bytes = config.copy_in_max_bytes;
+        let mut received = 0u64;
+        let data = body.map(move |chunk| -> Result<Bytes, anyhow::Error> {
+            let chunk = chunk?;
+            received += chunk.len() as u64;
+            if received > max_bytes {
+                return Err(ApiError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("Request body exceeds the limit of {max_bytes} bytes"),
+                )
+                .into());
+            }
+            Ok(chunk)
+        });
+
+        if params.db.starts_with("postgres://") {
+            let b = self.postgres.load_full();
+            let rows_loaded = b
+                .copy_in(
+                    &params.db,
+                    &params.table,
+                    &params.columns(),
+                    params.format.unwrap_or_default(),
+                    params.header,
+                    data,
+                )
+                .await?;
+            Ok(Json(sql::CopyInResult { rows_loaded }).into_response())
+        } else {
+            Err(unsupported_database(&params.db, &["postgres"]).into())
+        }
+    }
+
+    /// Run a query and return the result as JSON, for embedding it in
+    /// another response.
+    ///
+    /// Only supports formats that produce a single JSON document.
+    async fn query_sql_json(
+        &self,
+        query: SqlQuery,
+        format: SqlOutputFormat,
+        options: OutputOptions,
+    ) -> Result<JsonValue, anyhow::Error> {
+        let mut body = self.query_sql(query, format, options).await?.into_body();
+        let mut buf = Vec::new();
+        while let Some(chunk) = body.data().await {
+            buf.extend_from_slice(&chunk?);
+        }
+        Ok(serde_json::from_slice(&buf)?)
+    }
+
+    /// Upload a query response body to the configured object store.
+    async fn export_response(&self, key: &str, res: Response) -> Result<Response, anyhow::Error> {
+        let Some(store) = self.export_store.load_full() else {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Exporting results is not enabled on this server".to_string(),
+            )
+            .into());
+        };
+
+        let location = store.upload_body(key, res.into_body()).await?;
+        Ok((StatusCode::ACCEPTED, Json(location)).into_response())
+    }
+
+    async fn query_sql_with_backend(
+        backend: &dyn SqlBackend,
         query: SqlQuery,
         format: SqlOutputFormat,
+        options: OutputOptions,
+        log: &QueryLog,
+        limit: &RowLimit,
     ) -> Result<Response, anyhow::Error> {
-        match format {
+        let (content_type, buf) = match format {
             SqlOutputFormat::Json => {
-                let items = backend.query_json_maps(query).await?;
-                let data = Json(items);
-                Ok(data.into_response())
+                let rows = limit
+                    .stream(backend.query_json_map_stream(query).await?)
+                    .await?;
+                let rows = log.clone().stream(rows);
+                // The hash header must be sent before the body, so hashed
+                // responses are always buffered.
+                let threshold = if options.hash || options.hash_only {
+                    None
+                } else {
+                    options.buffer_threshold
+                };
+                match buffering::json_array_body(rows, threshold).await? {
+                    JsonArrayBody::Buffered(buf) => ("application/json", buf),
+                    JsonArrayBody::Streamed(body) => {
+                        return Ok((
+                            [(axum::http::header::CONTENT_TYPE, "application/json")],
+                            body,
+                        )
+                            .into_response());
+                    }
+                }
             }
             SqlOutputFormat::JsonLines => {
-                // TODO: stream the body!
-                let items = backend.query_json_maps(query).await?;
-                let mut buf = Vec::<u8>::new();
-
-                for item in items {
-                    serde_json::to_writer(&mut buf, &item)?;
-                    buf.push(b'\n');
+                let rows = limit.stream_lazily(backend.query_json_map_stream(query).await?);
+                let rows = log.clone().stream(rows).chain(limit.trailer()).boxed();
+                let chunks = lines::json_lines(None, rows);
+                if !(options.hash || options.hash_only) {
+                    return Ok(Self::streamed_response(
+                        "application/json",
+                        chunks,
+                        &options,
+                    ));
                 }
-
-                let res = Response::builder()
-                    .header(axum::http::header::CONTENT_TYPE, "application/json")
-                    .body(Body::from(buf))
-                    .unwrap();
-                Ok(res.into_response())
+                ("application/json", chunks.try_concat().await?)
+            }
+            SqlOutputFormat::MessagePack => {
+                let mut items = backend.query_json_maps(query).await?;
+                limit.truncate(&mut items);
+                let buf = msgpack::to_vec(&items)?;
+                log.finish(items.len());
+                (msgpack::MESSAGE_PACK_CONTENT_TYPE, buf)
+            }
+            SqlOutputFormat::MessagePackLines => {
+                let rows = limit.stream_lazily(backend.query_json_map_stream(query).await?);
+                let rows = log.clone().stream(rows).chain(limit.trailer()).boxed();
+                let chunks = msgpack::message_pack_records(rows);
+                if !(options.hash || options.hash_only) {
+                    return Ok(Self::streamed_response(
+                        msgpack::MESSAGE_PACK_CONTENT_TYPE,
+                        chunks,
+                        &options,
+                    ));
+                }
+                (
+                    msgpack::MESSAGE_PACK_CONTENT_TYPE,
+                    chunks.try_concat().await?,
+                )
             }
             SqlOutputFormat::JsonColumns => {
-                let (_columns, items) = backend.query_column_arrays(query).await?;
-                let data = Json(items);
-                Ok(data.into_response())
+                let (_columns, mut items) = backend.query_column_arrays(query).await?;
+                limit.truncate(&mut items);
+                let buf = serde_json::to_vec(&items)?;
+                log.finish(items.len());
+                ("application/json", buf)
             }
             SqlOutputFormat::JsonColumnLines => {
-                // TODO: stream the body!
-                let (names, items) = backend.query_column_arrays(query).await?;
-                let mut buf = Vec::<u8>::new();
-
-                serde_json::to_writer(&mut buf, &names)?;
-                buf.push(b'\n');
-
-                for item in items {
-                    serde_json::to_writer(&mut buf, &item)?;
-                    buf.push(b'\n');
+                let (names, rows) = backend.query_column_array_stream(query).await?;
+                let rows = log.clone().stream(limit.stream_lazily(rows));
+                let rows = rows.chain(limit.trailer()).boxed();
+                let chunks = lines::json_lines(Some(names.into()), rows);
+                if !(options.hash || options.hash_only) {
+                    return Ok(Self::streamed_response(
+                        "application/json",
+                        chunks,
+                        &options,
+                    ));
                 }
-
-                let res = Response::builder()
-                    .header(axum::http::header::CONTENT_TYPE, "application/json")
-                    .body(Body::from(buf))
-                    .unwrap();
-                Ok(res.into_response())
+                ("application/json", chunks.try_concat().await?)
+            }
+            SqlOutputFormat::EventStream => {
+                if options.hash || options.hash_only {
+                    return Err(ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        "Result hashes are not supported with the event-stream format".to_string(),
+                    )
+                    .into());
+                }
+                let rows = limit.stream_lazily(backend.query_json_map_stream(query).await?);
+                let events = lines::row_events(log.clone().stream(rows), limit.truncated_flag());
+                return Ok(Sse::new(events).into_response());
+            }
+            SqlOutputFormat::JsonTable => {
+                let (columns, mut rows) = backend.query_column_arrays(query).await?;
+                limit.truncate(&mut rows);
+                let count = rows.len();
+                let table = serde_json::json!({ "columns": columns, "rows": rows });
+                let buf = serde_json::to_vec(&table)?;
+                log.finish(count);
+                ("application/json", buf)
+            }
+            SqlOutputFormat::Csv => {
+                let (names, mut rows) = backend.query_column_arrays(query).await?;
+                limit.truncate(&mut rows);
+                log.finish(rows.len());
+                (
+                    csv::CSV_CONTENT_TYPE,
+                    csv::write_csv(&names, &rows, &options.null_string),
+                )
+            }
+            SqlOutputFormat::Parquet => {
+                let (columns, mut rows) = backend.query_typed_columns(query).await?;
+                limit.truncate(&mut rows);
+                let batch = columnar::record_batch(&columns, &rows)?;
+                let buf = columnar::write_parquet(&batch)?;
+                log.finish(rows.len());
+                (columnar::PARQUET_CONTENT_TYPE, buf)
             }
+            SqlOutputFormat::Arrow => {
+                let (columns, mut rows) = backend.query_typed_columns(query).await?;
+                limit.truncate(&mut rows);
+                let batch = columnar::record_batch(&columns, &rows)?;
+                let buf = columnar::write_arrow_stream(&batch)?;
+                log.finish(rows.len());
+                (columnar::ARROW_STREAM_CONTENT_TYPE, buf)
+            }
+        };
+
+        let mut res = Response::builder().header(axum::http::header::CONTENT_TYPE, content_type);
+        if options.hash || options.hash_only {
+            let hash = format!("{:x}", Sha256::digest(&buf));
+            res = res.header(RESULT_HASH_HEADER, hash);
         }
+
+        let body = if options.hash_only {
+            Body::empty()
+        } else {
+            Body::from(buf)
+        };
+        Ok(res.body(body).unwrap().into_response())
+    }
+
+    /// Build a chunked response that is sent while the chunks are produced.
+    ///
+    /// With a [`OutputOptions::flush_interval`], chunks are coalesced into
+    /// larger writes.
+    fn streamed_response<S>(
+        content_type: &'static str,
+        chunks: S,
+        options: &OutputOptions,
+    ) -> Response
+    where
+        S: futures::Stream<Item = Result<Vec<u8>, anyhow::Error>> + Send + 'static,
+    {
+        let body = match options.flush_interval {
+            Some(interval) => Body::wrap_stream(buffering::flush_interval(chunks, interval)),
+            None => Body::wrap_stream(chunks),
+        };
+        ([(axum::http::header::CONTENT_TYPE, content_type)], body).into_response()
+    }
+}
+
+/// Error for a database type that is not supported by an endpoint.
+fn unsupported_database(db: &str, supported: &[&str]) -> ApiError {
+    // Only the scheme is included, the URI may contain credentials.
+    let scheme = db.split_once(':').map_or(db, |(scheme, _)| scheme);
+    ApiError::new(
+        StatusCode::BAD_REQUEST,
+        format!(
+            "Unsupported database type '{scheme}', supported types are: {}",
+            supported.join(", ")
+        ),
+    )
+}
+
+fn stored_queries_only(alias: &str) -> ApiError {
+    ApiError::new(
+        StatusCode::FORBIDDEN,
+        format!("Database '{alias}' only allows stored queries"),
+    )
+}
+
+fn unknown_alias(alias: &str) -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        format!("Unknown database alias '{alias}'"),
+    )
+}
+
+/// Keep the current value of a setting that can not be changed without a
+/// restart, and warn if the reloaded config changes it.
+fn keep_restart_only<T: PartialEq + Clone>(field: &str, value: &mut T, current: &T) {
+    if value != current {
+        tracing::warn!("Changing {} requires a restart, ignoring", field);
+        *value = current.clone();
     }
 }
 
+/// Register the backends of all supported databases.
+fn register_backends(
+    postgres: &Arc<PostgresProx>,
+    mysql: &Arc<MysqlProx>,
+    sqlite: &Arc<SqliteProx>,
+) -> Backends {
+    let mut backends = Backends::default();
+    backends.register("postgres", postgres.clone());
+    backends.register("mysql", mysql.clone());
+    backends.register("sqlite", sqlite.clone());
+    backends
+}
+
+fn is_connection_uri(db: &str) -> bool {
+    db.contains("://") || SqliteProx::is_sqlite_uri(db)
+}
+
+/// Response header carrying the hex-encoded SHA-256 of the serialized result.
+const RESULT_HASH_HEADER: &str = "x-result-hash";
+
+/// The routes served on a listen address.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum Routes {
+    /// All routes, if no separate admin listen address is configured.
+    All,
+    /// The `/sql/*` routes.
+    Data,
+    /// Health checks, metrics and version.
+    Admin,
+}
+
 fn build_router(ctx: Ctx) -> Router {
-    Router::<Ctx>::new()
+    build_routes(ctx, Routes::All)
+}
+
+fn build_routes(ctx: Ctx, routes: Routes) -> Router {
+    let max_body_size = ctx.config.load().max_body_size;
+    let serves_data = routes != Routes::Admin;
+    let serves_admin = routes != Routes::Data;
+
+    let mut limited = Router::<Ctx, Limited<Body>>::new();
+    if serves_data {
+        limited = sql_routes(limited);
+    }
+    if serves_admin {
+        limited = limited
+            .route("/metrics", get(metrics::handler_metrics))
+            .route("/version", get(version::handler_version));
+    }
+    // The body limit is checked before bodies are parsed, and replaces the
+    // default limit of the extractors.
+    let limited = limited
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(max_body_size));
+
+    let mut authenticated = Router::<Ctx>::new().merge(limited);
+    if serves_data {
+        authenticated = authenticated
+            // Streamed into the database, and limited by
+            // `ServerConfig::copy_in_max_bytes` instead.
+            .route("/sql/copy-in", post(sql::handler_sql_copy_in))
+            // Messages are limited by the WebSocket protocol instead.
+            .route("/sql/ws", get(websocket::handler_sql_ws));
+    }
+    let authenticated = authenticated
+        // Route layers run in reverse order, so clients are authenticated
+        // before they are rate limited by token.
+        .route_layer(axum::middleware::from_fn_with_state(
+            ctx.clone(),
+            rate_limit::rate_limit,
+        ))
+        .route_layer(axum::middleware::from_fn_with_state(
+            ctx.clone(),
+            auth::authenticate,
+        ));
+
+    let compression = ctx.config.load().compression.clone();
+    let cors = ctx.cors.clone();
+
+    let mut router = Router::<Ctx>::new().merge(authenticated);
+    if serves_admin {
+        // Health checks stay open for load balancers and orchestrators.
+        router = router
+            .route("/health", get(health::handler_health))
+            .route("/health/ready", get(health::handler_health_ready));
+    }
+    let router = router
+        .layer(axum::middleware::from_fn(request_id::request_id))
+        .with_state(ctx);
+
+    let router = if compression.enabled {
+        // Streamed bodies have no size hint, so they are always compressed,
+        // and encoded chunk by chunk as rows arrive.
+        let predicate = SizeAbove::new(compression.min_size_bytes)
+            .and(NotForContentType::GRPC)
+            .and(NotForContentType::IMAGES)
+            .and(NotForContentType::const_new("text/event-stream"));
+        router.layer(CompressionLayer::new().compress_when(predicate))
+    } else {
+        router
+    };
+
+    // The outermost layer, so preflight requests are answered before routing
+    // and authentication.
+    match cors {
+        Some(cors) => router.layer(cors),
+        None => router,
+    }
+}
+
+/// The `/sql/*` routes with limited request bodies.
+fn sql_routes(router: Router<Ctx, Limited<Body>>) -> Router<Ctx, Limited<Body>> {
+    let router = router
         .route(
             "/sql/query",
             get(sql::handler_sql_query_get).post(sql::handler_sql_query_post),
         )
-        .with_state(ctx)
+        .route(
+            "/sql/describe",
+            get(sql::handler_sql_describe_get).post(sql::handler_sql_describe_post),
+        )
+        .route(
+            "/sql/copy-out",
+            get(sql::handler_sql_copy_out_get).post(sql::handler_sql_copy_out_post),
+        )
+        // Alias of `/sql/copy-out`.
+        .route(
+            "/sql/copy",
+            get(sql::handler_sql_copy_out_get).post(sql::handler_sql_copy_out_post),
+        )
+        .route(
+            "/sql/validate",
+            get(sql::handler_sql_validate_get).post(sql::handler_sql_validate_post),
+        )
+        .route(
+            "/sql/explain",
+            get(sql::handler_sql_explain_get).post(sql::handler_sql_explain_post),
+        )
+        .route("/sql/batch", post(sql::handler_sql_batch))
+        .route("/sql/schema", get(sql::handler_sql_schema))
+        .route("/sql/listen", get(listen::handler_sql_listen));
+    // Routes like `/sql/query.csv` select the output format by extension.
+    negotiate::EXTENSIONS
+        .iter()
+        .fold(router, |router, (ext, _)| {
+            router.route(
+                &format!("/sql/query.{ext}"),
+                get(sql::handler_sql_query_get).post(sql::handler_sql_query_post),
+            )
+        })
 }
 
-pub async fn start(config: ServerConfig) -> Result<(), anyhow::Error> {
-    let ctx = Arc::new(ServerState { config });
-    let router = build_router(ctx.clone());
+/// Time to wait for connection pools to close after the server stopped.
+const POOL_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A configured server, ready to be started.
+pub struct Server {
+    ctx: Ctx,
+}
+
+impl Server {
+    pub fn new(config: ServerConfig) -> Result<Self, anyhow::Error> {
+        let ctx = Arc::new(ServerState::new(config)?);
+        Ok(Self { ctx })
+    }
 
-    tracing::info!(listen=%ctx.config.listen, "Starting server");
-    axum::Server::bind(&ctx.config.listen)
-        .serve(router.into_make_service())
+    /// A handle for updating the configuration while the server is running.
+    pub fn config_handle(&self) -> ConfigHandle {
+        ConfigHandle(self.ctx.clone())
+    }
+
+    pub async fn run(self) -> Result<(), anyhow::Error> {
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Run the server until the `shutdown` future completes.
+    ///
+    /// On shutdown, no new connections are accepted, and in-flight requests
+    /// can finish within [`ServerConfig::shutdown_timeout_ms`] before the
+    /// remaining connections are closed.
+    pub async fn run_until<F>(self, shutdown: F) -> Result<(), anyhow::Error>
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let config = self.ctx.config.load_full();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        // Shared by the servers of all listen addresses.
+        let signal = async move {
+            shutdown.await;
+            let _ = shutdown_tx.send(());
+        }
+        .boxed()
+        .shared();
+
+        if config.auth.tokens.is_empty() {
+            tracing::warn!(
+                "No auth tokens configured, the server accepts unauthenticated requests"
+            );
+        }
+        let tls = match &config.tls {
+            Some(tls) => Some(load_tls(tls).await?),
+            None => None,
+        };
+        tracing::info!(listen=%config.listen, tls=tls.is_some(), "Starting server");
+        let mut routers = Vec::new();
+        match &config.admin_listen {
+            Some(admin_listen) => {
+                tracing::info!(listen=%admin_listen, "Serving admin routes separately");
+                let router = build_routes(self.ctx.clone(), Routes::Data);
+                routers.extend(config.listen.iter().map(|listen| (listen, router.clone())));
+                let admin = build_routes(self.ctx.clone(), Routes::Admin);
+                routers.extend(admin_listen.iter().map(|listen| (listen, admin.clone())));
+            }
+            None => {
+                let router = build_router(self.ctx.clone());
+                routers.extend(config.listen.iter().map(|listen| (listen, router.clone())));
+            }
+        }
+        let servers = routers
+            .into_iter()
+            .map(|(listen, router)| serve(listen, &config, router, tls.clone(), signal.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut server = futures::future::try_join_all(servers).map_ok(|_| ());
+
+        tokio::select! {
+            res = &mut server => res.context("Server failed")?,
+            Ok(()) = shutdown_rx => {
+                let timeout =
+                    Duration::from_millis(self.ctx.config.load().shutdown_timeout_ms);
+                tracing::info!(?timeout, "Shutting down, waiting for in-flight requests");
+                match tokio::time::timeout(timeout, &mut server).await {
+                    Ok(res) => res.context("Server failed")?,
+                    Err(_) => tracing::warn!("Shutdown timed out, closing remaining connections"),
+                }
+            }
+        }
+
+        for listen in config.listen_addrs() {
+            if let ListenAddr::Unix(path) = listen {
+                if let Err(err) = std::fs::remove_file(path) {
+                    tracing::warn!(path=%path.display(), error=%err, "Could not remove Unix socket");
+                }
+            }
+        }
+        if tokio::time::timeout(POOL_CLOSE_TIMEOUT, self.ctx.close())
+            .await
+            .is_err()
+        {
+            tracing::warn!("Timed out closing database connections");
+        }
+        Ok(())
+    }
+}
+
+/// Serve the router on a single listen address until `signal` completes.
+fn serve<S>(
+    listen: &ListenAddr,
+    config: &ServerConfig,
+    router: Router,
+    tls: Option<RustlsConfig>,
+    signal: S,
+) -> Result<Pin<Box<dyn Future<Output = Result<(), anyhow::Error>> + Send>>, anyhow::Error>
+where
+    S: Future<Output = ()> + Send + 'static,
+{
+    let server: Pin<Box<dyn Future<Output = Result<(), anyhow::Error>> + Send>> =
+        match (listen, tls) {
+            (ListenAddr::Tcp(addr), None) => Box::pin(
+                axum::Server::bind(addr)
+                    .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+                    .with_graceful_shutdown(signal)
+                    .err_into(),
+            ),
+            (ListenAddr::Tcp(addr), Some(tls)) => {
+                let handle = axum_server::Handle::new();
+                let shutdown_handle = handle.clone();
+                tokio::spawn(async move {
+                    signal.await;
+                    shutdown_handle.graceful_shutdown(None);
+                });
+                Box::pin(
+                    axum_server::bind_rustls(*addr, tls)
+                        .handle(handle)
+                        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+                        .err_into(),
+                )
+            }
+            #[cfg(unix)]
+            (ListenAddr::Unix(path), None) => Box::pin(
+                axum::Server::builder(unix::bind(path, config.unix_socket_mode)?)
+                    .serve(router.into_make_service())
+                    .with_graceful_shutdown(signal)
+                    .err_into(),
+            ),
+            #[cfg(not(unix))]
+            (ListenAddr::Unix(_), None) => {
+                bail!("Unix sockets are not supported on this platform")
+            }
+            (ListenAddr::Unix(_), Some(_)) => bail!("TLS is not supported on Unix sockets"),
+        };
+    Ok(server)
+}
+
+/// Load the certificate and key for serving HTTPS.
+async fn load_tls(config: &TlsConfig) -> Result<RustlsConfig, anyhow::Error> {
+    RustlsConfig::from_pem_file(&config.cert_path, &config.key_path)
         .await
-        .context("Server failed")?;
+        .with_context(|| {
+            format!(
+                "Could not load TLS certificate '{}' and key '{}'",
+                config.cert_path.display(),
+                config.key_path.display()
+            )
+        })
+}
 
-    Ok(())
+/// Handle for reloading the configuration of a running [`Server`].
+#[derive(Clone)]
+pub struct ConfigHandle(Ctx);
+
+impl ConfigHandle {
+    /// Apply a new configuration.
+    ///
+    /// Settings documented to require a restart, like the listen address,
+    /// keep their current value.
+    /// If the new configuration is invalid, an error is returned and the
+    /// current configuration stays active.
+    pub fn reload(&self, config: ServerConfig) -> Result<(), anyhow::Error> {
+        self.0.reload_config(config)
+    }
+}
+
+pub async fn start(config: ServerConfig) -> Result<(), anyhow::Error> {
+    Server::new(config)?.run().await
 }
 
 pub struct HandlerError(pub anyhow::Error);
 
+impl HandlerError {
+    /// Convert an error into an [`ApiError`] with the given level of detail.
+    fn with_verbosity(err: anyhow::Error, verbosity: ErrorVerbosity, query: Option<&str>) -> Self {
+        Self(ApiError::from_error(err, verbosity, query).into())
+    }
+}
+
 impl From<anyhow::Error> for HandlerError {
     fn from(e: anyhow::Error) -> Self {
         Self(e)
@@ -149,7 +1155,11 @@ impl axum::response::IntoResponse for HandlerError {
 struct ApiResponse<T = ()> {
     #[serde(skip_serializing_if = "Option::is_none")]
     data: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     errors: Option<Vec<HttpApiError>>,
+    /// Notices sent by the database while running the query.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    notices: Vec<Notice>,
 }
 
 impl<T> ApiResponse<T> {
@@ -157,6 +1167,15 @@ impl<T> ApiResponse<T> {
         Self {
             data: Some(data),
             errors: None,
+            notices: Vec::new(),
+        }
+    }
+
+    fn from_error(err: ApiError) -> Self {
+        Self {
+            data: None,
+            errors: Some(vec![err.into()]),
+            notices: Vec::new(),
         }
     }
 }
@@ -166,6 +1185,7 @@ impl<T> From<T> for ApiResponse<T> {
         Self {
             data: Some(data),
             errors: None,
+            notices: Vec::new(),
         }
     }
 }
@@ -174,14 +1194,84 @@ impl<T> From<T> for ApiResponse<T> {
 pub struct ApiError {
     pub status: StatusCode,
     pub message: String,
+    /// SQLSTATE code of database errors.
+    pub code: Option<String>,
+    /// Additional information, only included with verbose errors.
+    pub details: Option<ErrorDetails>,
+    /// Seconds after which the client may retry, sent in the `Retry-After`
+    /// header.
+    pub retry_after: Option<u64>,
 }
 
 impl ApiError {
     pub fn new(status: StatusCode, message: String) -> Self {
-        Self { status, message }
+        Self {
+            status,
+            message,
+            code: None,
+            details: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn with_retry_after(mut self, secs: u64) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+
+    /// Build an error response with the configured level of detail.
+    ///
+    /// Errors that already are an [`ApiError`] are intended for clients and
+    /// are returned unchanged. Invalid arguments are reported as bad requests.
+    pub fn from_error(err: anyhow::Error, verbosity: ErrorVerbosity, query: Option<&str>) -> Self {
+        let err = match err.downcast::<ApiError>() {
+            Ok(api_err) => return api_err,
+            Err(err) => err,
+        };
+        if let Some(arg_err) = err.downcast_ref::<ArgumentError>() {
+            return Self::new(StatusCode::BAD_REQUEST, arg_err.to_string());
+        }
+
+        match verbosity {
+            ErrorVerbosity::Minimal => {
+                let mut api_err = Self::from(err);
+                api_err.message = "query failed".to_string();
+                api_err.code = None;
+                api_err
+            }
+            ErrorVerbosity::Standard => Self::from(err),
+            ErrorVerbosity::Verbose => {
+                let db = err.downcast_ref::<DatabaseError>().cloned();
+                let message = format!("{:#}", err);
+                let mut api_err = Self::from(err);
+                api_err.message = message;
+                api_err.details = Some(ErrorDetails {
+                    detail: db.as_ref().and_then(|db| db.detail.clone()),
+                    hint: db.as_ref().and_then(|db| db.hint.clone()),
+                    position: db.as_ref().and_then(|db| db.position),
+                    query: query.map(|q| q.to_string()),
+                });
+                api_err
+            }
+        }
     }
 }
 
+/// Additional error information for verbose error responses.
+#[derive(serde::Serialize, PartialEq, Eq, Clone, Default, Debug)]
+pub struct ErrorDetails {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+    /// 1-based character position of the error in the query.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<u32>,
+    /// The query that failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+}
+
 impl std::fmt::Display for ApiError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{} ({})", self.message, self.status)
@@ -192,31 +1282,92 @@ impl std::error::Error for ApiError {}
 
 impl From<anyhow::Error> for ApiError {
     fn from(e: anyhow::Error) -> Self {
+        let db = e.downcast_ref::<DatabaseError>();
         Self {
-            status: StatusCode::INTERNAL_SERVER_ERROR,
+            status: db.map_or(StatusCode::INTERNAL_SERVER_ERROR, |db| {
+                database_error_status(db.kind)
+            }),
             message: e.to_string(),
+            code: db.and_then(|db| db.code.clone()),
+            details: None,
+            retry_after: None,
         }
     }
 }
 
+/// The response status for an error.
+fn error_status(err: &anyhow::Error) -> StatusCode {
+    if let Some(api_err) = err.downcast_ref::<ApiError>() {
+        return api_err.status;
+    }
+    if err.is::<ArgumentError>() {
+        return StatusCode::BAD_REQUEST;
+    }
+    match err.downcast_ref::<DatabaseError>() {
+        Some(db) => database_error_status(db.kind),
+        None => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// The response status for a database error.
+fn database_error_status(kind: DatabaseErrorKind) -> StatusCode {
+    match kind {
+        DatabaseErrorKind::InvalidQuery => StatusCode::BAD_REQUEST,
+        DatabaseErrorKind::UndefinedObject => StatusCode::NOT_FOUND,
+        DatabaseErrorKind::ConstraintViolation => StatusCode::CONFLICT,
+        DatabaseErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        DatabaseErrorKind::AuthenticationFailed => StatusCode::BAD_GATEWAY,
+        DatabaseErrorKind::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        DatabaseErrorKind::Other => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        let err = HttpApiError {
-            message: self.message,
-        };
-        (self.status, Json(err)).into_response()
+        let status = self.status;
+        let retry_after = self.retry_after;
+        let mut res = (status, Json(HttpApiError::from(self))).into_response();
+        if let Some(secs) = retry_after {
+            res.headers_mut().insert(
+                axum::http::header::RETRY_AFTER,
+                axum::http::HeaderValue::from(secs),
+            );
+        }
+        res
     }
 }
 
 #[derive(serde::Serialize, PartialEq, Eq, Clone, Debug)]
 struct HttpApiError {
     message: String,
+    /// SQLSTATE code of database errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    details: Option<ErrorDetails>,
+    /// Id of the failed request, as in the `X-Request-Id` response header.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl From<ApiError> for HttpApiError {
+    fn from(err: ApiError) -> Self {
+        Self {
+            message: err.message,
+            code: err.code,
+            details: err.details,
+            request_id: request_id::current(),
+        }
+    }
 }
 
 impl HttpApiError {
     fn from_anyhow(err: anyhow::Error) -> Self {
         Self {
             message: err.to_string(),
+            code: None,
+            details: None,
+            request_id: request_id::current(),
         }
     }
 
@@ -0,0 +1,23 @@
+//! MessagePack output formats.
+
+use daprox_core::JsonRowStream;
+use futures::{Stream, StreamExt as _};
+use serde_json::Value as JsonValue;
+
+pub(super) const MESSAGE_PACK_CONTENT_TYPE: &str = "application/msgpack";
+
+/// Serialize a value to MessagePack, with maps keyed by field name.
+pub(super) fn to_vec(value: &impl serde::Serialize) -> Result<Vec<u8>, anyhow::Error> {
+    Ok(rmp_serde::to_vec_named(value)?)
+}
+
+/// Serialize rows into chunks of one MessagePack value each.
+///
+/// MessagePack values are self-delimiting, so the records are written
+/// back-to-back without a separator.
+/// Rows are only fetched from the backend as the chunks are consumed.
+pub(super) fn message_pack_records(
+    rows: JsonRowStream,
+) -> impl Stream<Item = Result<Vec<u8>, anyhow::Error>> + Send + 'static {
+    rows.map(|value: Result<JsonValue, anyhow::Error>| to_vec(&value?))
+}
@@ -0,0 +1,98 @@
+//! Selection of the output format from the request path and `Accept` header.
+
+use axum::http::{header, HeaderMap, StatusCode};
+
+use super::{
+    columnar::{ARROW_STREAM_CONTENT_TYPE, PARQUET_CONTENT_TYPE},
+    csv::CSV_CONTENT_TYPE,
+    msgpack::MESSAGE_PACK_CONTENT_TYPE,
+    sql::SqlOutputFormat,
+    ApiError,
+};
+
+/// File extensions of `/sql/query.<ext>` routes, and their formats.
+pub(super) const EXTENSIONS: &[(&str, SqlOutputFormat)] = &[
+    ("json", SqlOutputFormat::Json),
+    ("jsonl", SqlOutputFormat::JsonLines),
+    ("ndjson", SqlOutputFormat::JsonLines),
+    ("msgpack", SqlOutputFormat::MessagePack),
+    ("csv", SqlOutputFormat::Csv),
+    ("parquet", SqlOutputFormat::Parquet),
+    ("arrow", SqlOutputFormat::Arrow),
+];
+
+/// Media types accepted in the `Accept` header, and their formats.
+const MEDIA_TYPES: &[(&str, SqlOutputFormat)] = &[
+    ("application/json", SqlOutputFormat::Json),
+    ("application/x-ndjson", SqlOutputFormat::JsonLines),
+    ("application/jsonl", SqlOutputFormat::JsonLines),
+    (MESSAGE_PACK_CONTENT_TYPE, SqlOutputFormat::MessagePack),
+    ("application/x-msgpack", SqlOutputFormat::MessagePack),
+    (CSV_CONTENT_TYPE, SqlOutputFormat::Csv),
+    (PARQUET_CONTENT_TYPE, SqlOutputFormat::Parquet),
+    (ARROW_STREAM_CONTENT_TYPE, SqlOutputFormat::Arrow),
+    ("text/event-stream", SqlOutputFormat::EventStream),
+];
+
+/// Select the output format of a query request that has no explicit
+/// `format`.
+///
+/// A file extension of the path takes precedence over the `Accept` header.
+/// Returns `None` if neither selects a format, so the default is used.
+pub(super) fn negotiate_format(
+    path: &str,
+    headers: &HeaderMap,
+) -> Result<Option<SqlOutputFormat>, ApiError> {
+    if let Some((_, ext)) = path.rsplit_once('.') {
+        if let Some((_, format)) = EXTENSIONS.iter().find(|(e, _)| *e == ext) {
+            return Ok(Some(format.clone()));
+        }
+    }
+
+    let Some(accept) = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Ok(None);
+    };
+    accept_format(accept)
+}
+
+/// Select the format of the most preferred supported media type.
+///
+/// A wildcard like `*/*` selects the default format, so unsupported media
+/// types only fail the request with `406 Not Acceptable` if the client does
+/// not accept any other type.
+fn accept_format(accept: &str) -> Result<Option<SqlOutputFormat>, ApiError> {
+    let mut ranges = accept
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let media_type = parts.next().filter(|t| !t.is_empty())?.to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((media_type, quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect::<Vec<_>>();
+    if ranges.is_empty() {
+        return Ok(None);
+    }
+    // Stable, so equally preferred types keep the order of the header.
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    for (media_type, _) in &ranges {
+        if media_type.ends_with("/*") {
+            return Ok(None);
+        }
+        if let Some((_, format)) = MEDIA_TYPES.iter().find(|(t, _)| t == media_type) {
+            return Ok(Some(format.clone()));
+        }
+    }
+    Err(ApiError::new(
+        StatusCode::NOT_ACCEPTABLE,
+        format!("None of the accepted media types '{accept}' is supported"),
+    ))
+}
@@ -0,0 +1,190 @@
+//! Restriction of clients to read-only statements.
+
+use axum::http::StatusCode;
+use daprox_core::{
+    lexer::{self, Token, TokenKind},
+    SqlQuery,
+};
+
+use super::{ApiError, ClientToken, ServerState};
+use crate::config::ReadOnlyMode;
+
+/// Leading keywords of statements that only read data.
+const READ_KEYWORDS: &[&str] = &["SELECT", "WITH", "EXPLAIN", "VALUES", "TABLE", "SHOW"];
+
+/// Keywords that modify data even inside read statements, like in
+/// data-modifying CTEs, `SELECT INTO` or `EXPLAIN ANALYZE INSERT`.
+const WRITE_KEYWORDS: &[&str] = &["INSERT", "UPDATE", "DELETE", "MERGE", "INTO"];
+
+impl ServerState {
+    /// The read-only mode that applies to a client.
+    ///
+    /// The mode of the client's token takes precedence over the global one.
+    pub(super) fn read_only_mode(&self, client: Option<&ClientToken>) -> ReadOnlyMode {
+        client
+            .and_then(|token| token.read_only)
+            .unwrap_or(self.config.load().read_only)
+    }
+
+    /// Enforce the read-only mode of the client on a query.
+    ///
+    /// With [`ReadOnlyMode::Transaction`], the query is also changed to run
+    /// in a read-only transaction.
+    pub(super) fn apply_read_only(
+        &self,
+        query: &mut SqlQuery,
+        client: Option<&ClientToken>,
+    ) -> Result<(), ApiError> {
+        match self.read_only_mode(client) {
+            ReadOnlyMode::Off => {}
+            ReadOnlyMode::Statements => check_read_only(query)?,
+            ReadOnlyMode::Transaction => {
+                check_read_only(query)?;
+                query.read_only_tx = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that the query only contains statements that read data.
+///
+/// Multiple statements are only allowed with
+/// [`SqlQuery::allow_multiple`].
+/// Errs on the side of rejecting statements that can not be classified.
+fn check_read_only(query: &SqlQuery) -> Result<(), ApiError> {
+    let statements = lexer::statements(&query.query);
+    if statements.len() > 1 && !query.allow_multiple {
+        return Err(forbidden(
+            "Multiple statements are not allowed in read-only mode".to_string(),
+        ));
+    }
+
+    for statement in &statements {
+        match find_write(statement) {
+            Some(Write::Statement(keyword)) => {
+                return Err(forbidden(format!(
+                    "Only read statements are allowed in read-only mode, got '{keyword}'"
+                )));
+            }
+            Some(Write::Keyword(keyword)) => {
+                return Err(forbidden(format!(
+                    "'{keyword}' is not allowed in read-only mode"
+                )));
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Check that a query sent with a GET request only reads data.
+///
+/// Queries in URLs end up in logs and browser histories, and GET requests
+/// must not have side effects, so writes require a POST request.
+pub(super) fn check_get_query(query: &SqlQuery) -> Result<(), ApiError> {
+    for statement in &lexer::statements(&query.query) {
+        if let Some(Write::Statement(keyword) | Write::Keyword(keyword)) = find_write(statement) {
+            return Err(ApiError::new(
+                StatusCode::METHOD_NOT_ALLOWED,
+                format!("'{keyword}' is not allowed in GET requests, use POST for writes"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The reason a statement does not only read data.
+enum Write<'a> {
+    /// The statement does not start with a read keyword.
+    Statement(&'a str),
+    /// The statement contains a keyword that modifies data.
+    Keyword(&'a str),
+}
+
+fn find_write<'a>(statement: &[Token<'a>]) -> Option<Write<'a>> {
+    let keyword = statement[0].text.to_ascii_uppercase();
+    if statement[0].kind != TokenKind::Word || !READ_KEYWORDS.contains(&keyword.as_str()) {
+        return Some(Write::Statement(statement[0].text));
+    }
+
+    statement
+        .iter()
+        .find(|token| {
+            token.kind == TokenKind::Word
+                && WRITE_KEYWORDS.contains(&token.text.to_ascii_uppercase().as_str())
+        })
+        .map(|token| Write::Keyword(token.text))
+}
+
+fn forbidden(message: String) -> ApiError {
+    ApiError::new(StatusCode::FORBIDDEN, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(sql: &str) -> SqlQuery {
+        SqlQuery {
+            query: sql.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_read_only_rejects_nested_writes() {
+        for (sql, keyword) in [
+            (
+                "WITH d AS (DELETE FROM t RETURNING *) SELECT * FROM d",
+                "DELETE",
+            ),
+            ("EXPLAIN ANALYZE UPDATE t SET a = 1", "UPDATE"),
+            ("SELECT * INTO backup FROM t", "INTO"),
+            (
+                "with x as (select 1) insert into t select * from x",
+                "insert",
+            ),
+        ] {
+            let err = check_read_only(&query(sql)).unwrap_err();
+            assert_eq!(err.status, StatusCode::FORBIDDEN, "{sql}");
+            assert!(err.message.contains(&format!("'{keyword}'")), "{sql}");
+
+            let err = check_get_query(&query(sql)).unwrap_err();
+            assert_eq!(err.status, StatusCode::METHOD_NOT_ALLOWED, "{sql}");
+        }
+
+        let err = check_read_only(&query("DROP TABLE t")).unwrap_err();
+        assert!(err.message.contains("got 'DROP'"));
+    }
+
+    #[test]
+    fn test_read_only_ignores_comments_and_quoted_keywords() {
+        for sql in [
+            "-- DELETE FROM t\nSELECT 1",
+            "/* UPDATE t SET a = 1; */ SELECT 1",
+            "SELECT 1 /* INSERT */ -- INTO",
+            "SELECT 'delete', E'update\\'s' AS \"insert\" FROM \"into\"",
+            "SELECT $$DELETE FROM t$$",
+        ] {
+            assert!(check_read_only(&query(sql)).is_ok(), "{sql}");
+            assert!(check_get_query(&query(sql)).is_ok(), "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_read_only_multiple_statements() {
+        let sql = "SELECT 1; SELECT 2";
+        assert!(check_read_only(&query(sql)).is_err());
+        let multiple = SqlQuery {
+            allow_multiple: true,
+            ..query(sql)
+        };
+        assert!(check_read_only(&multiple).is_ok());
+        assert!(check_read_only(&SqlQuery {
+            allow_multiple: true,
+            ..query("SELECT 1; DELETE FROM t")
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! Request rate limiting per client.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse as _, Response},
+};
+
+use super::{ApiError, ClientToken, Ctx};
+use crate::config::RateLimitConfig;
+
+/// Number of tracked clients above which idle ones are forgotten.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+/// Token buckets of all clients, keyed by token id or IP address.
+#[derive(Default, Debug)]
+pub(super) struct RateLimiter(Mutex<HashMap<String, Bucket>>);
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Add the tokens that accrued since the last update.
+    fn refill(&mut self, config: &RateLimitConfig, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * config.requests_per_second).min(config.burst as f64);
+        self.updated = now;
+    }
+}
+
+impl RateLimiter {
+    /// Take a token from the client's bucket.
+    ///
+    /// Returns the time until the next token is available if the bucket is
+    /// empty.
+    fn acquire(&self, key: &str, config: &RateLimitConfig) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.0.lock().unwrap();
+        if buckets.len() >= MAX_IDLE_BUCKETS && !buckets.contains_key(key) {
+            // Full buckets are the same as new ones, so they can be dropped.
+            buckets.retain(|_, bucket| {
+                bucket.refill(config, now);
+                bucket.tokens < config.burst as f64
+            });
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: config.burst as f64,
+            updated: now,
+        });
+        bucket.refill(config, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        let wait = (1.0 - bucket.tokens) / config.requests_per_second;
+        Err(Duration::from_secs_f64(wait))
+    }
+}
+
+/// Reject requests exceeding [`ServerConfig::rate_limit`] with 429.
+///
+/// Clients are identified by their auth token, or by their IP address for
+/// unauthenticated requests. Must run after [`super::auth::authenticate`].
+///
+/// [`ServerConfig::rate_limit`]: crate::config::ServerConfig::rate_limit
+pub(super) async fn rate_limit<B>(
+    State(ctx): State<Ctx>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(config) = ctx.config.load().rate_limit.clone() else {
+        return next.run(req).await;
+    };
+
+    let key = if let Some(token) = req.extensions().get::<ClientToken>() {
+        format!("token:{}", token.id)
+    } else if let Some(ConnectInfo(addr)) = req.extensions().get::<ConnectInfo<SocketAddr>>() {
+        format!("ip:{}", addr.ip())
+    } else {
+        // Only happens if the server is not run with connection info.
+        "unknown".to_string()
+    };
+
+    match ctx.rate_limiter.acquire(&key, &config) {
+        Ok(()) => next.run(req).await,
+        Err(wait) => {
+            let secs = wait.as_secs_f64().ceil().max(1.0) as u64;
+            ApiError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Rate limit exceeded, retry later".to_string(),
+            )
+            .with_retry_after(secs)
+            .into_response()
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Request ids for correlating responses with log lines.
+
+use axum::{
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument as _;
+
+/// Header with the id of a request, set on all responses.
+///
+/// Taken from the request if the client sent a valid one.
+pub(super) const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Maximum length of request ids accepted from clients.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The id of the request that is currently handled, if any.
+pub(super) fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Assign an id to the request, record it in the tracing span of the
+/// request and echo it in the response.
+pub(super) async fn request_id<B>(req: Request<B>, next: Next<B>) -> Response {
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+        path = req.uri().path(),
+    );
+    let mut res = REQUEST_ID
+        .scope(id.clone(), next.run(req).instrument(span))
+        .await;
+    // Only ids made of visible ASCII characters are accepted, so this never
+    // fails.
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    res
+}
+
+/// Ids from clients end up in logs, so they are restricted to a reasonable
+/// length of visible ASCII characters.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
@@ -0,0 +1,111 @@
+//! Enforcement of [`ServerConfig::max_rows`](crate::config::ServerConfig::max_rows).
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use daprox_core::JsonRowStream;
+use futures::{Stream, StreamExt as _, TryStreamExt as _};
+use serde_json::Value as JsonValue;
+
+/// Response header that is set to `true` if rows were omitted because the
+/// result exceeded the row limit.
+pub(super) const TRUNCATED_HEADER: &str = "x-result-truncated";
+
+/// Key of the record that ends truncated results of line-based formats.
+pub(super) const TRUNCATED_RECORD_KEY: &str = "$truncated";
+
+/// Limits the rows of a query result, and records whether any were dropped.
+#[derive(Debug)]
+pub(super) struct RowLimit {
+    max_rows: Option<usize>,
+    /// Shared with lazily limited streams.
+    truncated: Arc<AtomicBool>,
+}
+
+impl RowLimit {
+    pub fn new(max_rows: Option<usize>) -> Self {
+        Self {
+            max_rows,
+            truncated: Default::default(),
+        }
+    }
+
+    /// Whether rows were dropped from the result.
+    pub fn truncated(&self) -> bool {
+        self.truncated.load(Ordering::Relaxed)
+    }
+
+    /// Drop the rows beyond the limit.
+    pub fn truncate<T>(&self, rows: &mut Vec<T>) {
+        if let Some(max_rows) = self.max_rows {
+            if rows.len() > max_rows {
+                rows.truncate(max_rows);
+                self.truncated.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Limit a stream of rows, for formats that signal the truncation with
+    /// [`TRUNCATED_HEADER`].
+    ///
+    /// Whether the result is truncated must be known before the response
+    /// headers are sent, so with a limit up to `max_rows + 1` rows are read
+    /// before returning. Streams are returned as is without a limit.
+    pub async fn stream(&self, rows: JsonRowStream) -> Result<JsonRowStream, anyhow::Error> {
+        let Some(max_rows) = self.max_rows else {
+            return Ok(rows);
+        };
+        let mut rows = rows
+            .take(max_rows.saturating_add(1))
+            .try_collect::<Vec<_>>()
+            .await?;
+        self.truncate(&mut rows);
+        Ok(futures::stream::iter(rows.into_iter().map(Ok)).boxed())
+    }
+
+    /// Limit a stream of rows as it is read.
+    ///
+    /// For formats that start sending rows before the truncation is known,
+    /// and signal it after the rows instead, like with [`Self::trailer`].
+    pub fn stream_lazily(&self, rows: JsonRowStream) -> JsonRowStream {
+        let Some(max_rows) = self.max_rows else {
+            return rows;
+        };
+        let truncated = self.truncated.clone();
+        rows.take(max_rows.saturating_add(1))
+            .enumerate()
+            .filter_map(move |(index, row)| {
+                // Only the row beyond the limit was read, so the query can
+                // be cancelled right after.
+                let row = match row {
+                    Ok(_) if index == max_rows => {
+                        truncated.store(true, Ordering::Relaxed);
+                        None
+                    }
+                    row => Some(row),
+                };
+                futures::future::ready(row)
+            })
+            .boxed()
+    }
+
+    /// Flag that is set once rows of a lazily limited stream were dropped.
+    pub fn truncated_flag(&self) -> Arc<AtomicBool> {
+        self.truncated.clone()
+    }
+
+    /// A record to append to the rows of line-based formats, which is only
+    /// produced if rows of a lazily limited stream were dropped.
+    pub fn trailer(&self) -> impl Stream<Item = Result<JsonValue, anyhow::Error>> + Send + 'static {
+        let truncated = self.truncated.clone();
+        futures::stream::once(futures::future::lazy(move |_| {
+            truncated.load(Ordering::Relaxed)
+        }))
+        .filter_map(|truncated| {
+            let record = serde_json::json!({ TRUNCATED_RECORD_KEY: true });
+            futures::future::ready(truncated.then(|| Ok(record)))
+        })
+    }
+}
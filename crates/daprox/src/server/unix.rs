@@ -0,0 +1,77 @@
+//! Serving over Unix domain sockets.
+
+use std::{
+    io,
+    os::unix::fs::{FileTypeExt as _, PermissionsExt as _},
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use anyhow::{bail, Context as _};
+use tokio::net::{UnixListener, UnixStream};
+
+/// Bind a Unix socket at `path` and restrict its permissions to `mode`.
+///
+/// A socket file left behind by a previous run is removed first.
+pub(super) fn bind(path: &Path, mode: u32) -> Result<UnixAccept, anyhow::Error> {
+    remove_stale_socket(path)?;
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Could not bind Unix socket '{}'", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).with_context(|| {
+        format!(
+            "Could not set permissions of Unix socket '{}'",
+            path.display()
+        )
+    })?;
+    Ok(UnixAccept(listener))
+}
+
+/// Remove the socket file at `path` if no server is listening on it.
+///
+/// Fails if the socket is still in use, or if the path is not a socket, so
+/// unrelated files are never deleted.
+fn remove_stale_socket(path: &Path) -> Result<(), anyhow::Error> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Could not access '{}'", path.display()))
+        }
+    };
+    if !metadata.file_type().is_socket() {
+        bail!("'{}' already exists and is not a socket", path.display());
+    }
+
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => bail!(
+            "Unix socket '{}' is in use by another process",
+            path.display()
+        ),
+        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+            tracing::info!(path=%path.display(), "Removing stale Unix socket");
+            std::fs::remove_file(path)
+                .with_context(|| format!("Could not remove stale socket '{}'", path.display()))
+        }
+        Err(err) => {
+            Err(err).with_context(|| format!("Could not check Unix socket '{}'", path.display()))
+        }
+    }
+}
+
+/// Accepts connections on a Unix socket for [`axum::Server`].
+pub(super) struct UnixAccept(UnixListener);
+
+impl hyper::server::accept::Accept for UnixAccept {
+    type Conn = UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.0
+            .poll_accept(cx)
+            .map(|res| Some(res.map(|(stream, _addr)| stream)))
+    }
+}
@@ -0,0 +1,38 @@
+//! Version and build information.
+
+use axum::{extract::State, Json};
+use chrono::{TimeZone as _, Utc};
+
+use super::AppState;
+
+/// Information about the running build.
+#[derive(serde::Serialize, Clone, Debug)]
+pub(super) struct BuildInfo {
+    version: &'static str,
+    /// Git commit the server was built from, or `unknown` if built outside
+    /// of a git checkout.
+    git_commit: &'static str,
+    /// When the build script last ran, as an RFC 3339 timestamp.
+    build_timestamp: Option<String>,
+    /// URI schemes of the compiled-in database backends.
+    backends: Vec<&'static str>,
+    /// Whether Postgres connections support TLS via rustls.
+    postgres_tls: bool,
+}
+
+/// Report the version and build details of the server.
+pub(super) async fn handler_version(State(ctx): AppState) -> Json<BuildInfo> {
+    let build_timestamp = env!("DAPROX_BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+        .map(|time| time.to_rfc3339());
+
+    Json(BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("DAPROX_GIT_COMMIT"),
+        build_timestamp,
+        backends: ctx.backends.load().schemes(),
+        postgres_tls: daprox_postgres::TLS_SUPPORT,
+    })
+}
@@ -0,0 +1,182 @@
+//! Streaming query results over WebSockets.
+//!
+//! Clients send a [`SqlQuery`] as a JSON text message, and receive each row
+//! as a separate message as soon as it is read from the database, followed
+//! by a `complete` or `error` message. Queries on a socket run one at a time.
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::StatusCode,
+    response::Response,
+    Extension,
+};
+use daprox_core::SqlQuery;
+use futures::StreamExt as _;
+use serde_json::Value as JsonValue;
+
+use super::{
+    error_status,
+    sql::{OutputOptions, SqlOutputFormat},
+    ApiError, AppState, ClientToken, Ctx, HttpApiError, QueryLog,
+};
+
+/// A message sent to the client.
+#[derive(serde::Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    /// A single row, as an object keyed by column name.
+    Row { data: JsonValue },
+    /// All rows were sent.
+    Complete {
+        rows: usize,
+        /// Whether rows were omitted because of the row limit.
+        truncated: bool,
+    },
+    /// The query failed, possibly after some rows were already sent.
+    Error {
+        #[serde(flatten)]
+        error: HttpApiError,
+    },
+}
+
+impl ServerMessage {
+    fn to_message(&self) -> Message {
+        Message::Text(serde_json::to_string(self).unwrap())
+    }
+}
+
+pub(super) async fn handler_sql_ws(
+    State(ctx): AppState,
+    client: Option<Extension<ClientToken>>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let client = client.map(|Extension(client)| client);
+    ws.on_upgrade(move |socket| serve(ctx, client, socket))
+}
+
+/// Run the queries sent on the socket until the client disconnects.
+async fn serve(ctx: Ctx, client: Option<ClientToken>, mut socket: WebSocket) {
+    while let Some(Ok(message)) = socket.recv().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => return,
+            // Pings are answered automatically.
+            _ => continue,
+        };
+
+        let error = match run_query(&ctx, client.as_ref(), &text, &mut socket).await {
+            Ok(Outcome::Complete) => continue,
+            Ok(Outcome::Disconnected) => return,
+            Err(err) => err,
+        };
+        let message = ServerMessage::Error {
+            error: HttpApiError::from(error),
+        };
+        if socket.send(message.to_message()).await.is_err() {
+            return;
+        }
+    }
+}
+
+enum Outcome {
+    Complete,
+    Disconnected,
+}
+
+/// Run a single query and send its rows.
+///
+/// Returns the error to send to the client if the query failed.
+async fn run_query(
+    ctx: &Ctx,
+    client: Option<&ClientToken>,
+    text: &str,
+    socket: &mut WebSocket,
+) -> Result<Outcome, ApiError> {
+    let mut query = serde_json::from_str::<SqlQuery>(text)
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid query: {err}")))?;
+    // Held until all rows were sent.
+    let _permits = ctx.acquire_query_permits(client)?;
+
+    let config = ctx.config.load_full();
+    let options = OutputOptions::embedded(&config, &query.db);
+    ctx.resolve_query(&mut query, None)?;
+    query
+        .validate()
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err.to_string()))?;
+    ctx.apply_read_only(&mut query, client)?;
+    ctx.check_database(&query.db)?;
+    let sql = query.query.clone();
+    let api_error =
+        |err: anyhow::Error| ApiError::from_error(err, config.error_verbosity, Some(&sql));
+
+    let log = QueryLog::start(
+        &query,
+        &SqlOutputFormat::JsonLines,
+        &options,
+        config.log_queries,
+    );
+    let max_rows = config.max_rows;
+    query.max_rows = max_rows;
+    let backend = ctx.backends.load().get(&query.db).map_err(api_error)?;
+    let mut rows = match backend.query_json_map_stream(query).await {
+        Ok(rows) => rows,
+        Err(err) => {
+            log.fail(&err, error_status(&err));
+            return Err(api_error(err));
+        }
+    };
+
+    let mut count = 0;
+    let truncated = loop {
+        tokio::select! {
+            row = rows.next() => match row {
+                Some(Ok(_)) if Some(count) == max_rows => break true,
+                Some(Ok(data)) => {
+                    count += 1;
+                    let message = ServerMessage::Row { data }.to_message();
+                    if socket.send(message).await.is_err() {
+                        log.finish(count);
+                        return Ok(Outcome::Disconnected);
+                    }
+                }
+                Some(Err(err)) => {
+                    log.fail(&err, error_status(&err));
+                    return Err(api_error(err));
+                }
+                None => break false,
+            },
+            message = socket.recv() => match message {
+                // Dropping the row stream cancels the query.
+                None | Some(Err(_)) | Some(Ok(Message::Close(_))) => {
+                    log.finish(count);
+                    return Ok(Outcome::Disconnected);
+                }
+                Some(Ok(Message::Text(_))) => {
+                    let error = ApiError::new(
+                        StatusCode::CONFLICT,
+                        "A query is already running on this socket".to_string(),
+                    );
+                    let message = ServerMessage::Error { error: error.into() };
+                    if socket.send(message.to_message()).await.is_err() {
+                        log.finish(count);
+                        return Ok(Outcome::Disconnected);
+                    }
+                }
+                Some(Ok(_)) => {}
+            },
+        }
+    };
+    log.finish(count);
+
+    let message = ServerMessage::Complete {
+        rows: count,
+        truncated,
+    };
+    if socket.send(message.to_message()).await.is_err() {
+        return Ok(Outcome::Disconnected);
+    }
+    Ok(Outcome::Complete)
+}
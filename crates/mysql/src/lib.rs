@@ -0,0 +1,297 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::bail;
+use async_trait::async_trait;
+use base64::Engine as _;
+use daprox_core::{ArgumentError, ColumnInfo, ColumnNames, ColumnType, SqlBackend, SqlQuery};
+use mysql_async::{
+    consts::{ColumnFlags, ColumnType as MysqlType},
+    prelude::Queryable,
+    Column, Params, Pool, Row, TxOpts, Value,
+};
+use serde_json::Value as JsonValue;
+
+/// Character set number of binary strings and blobs.
+const BINARY_CHARSET: u16 = 63;
+
+/// Backend for MySQL and MariaDB.
+#[derive(Default)]
+pub struct MysqlProx {
+    /// Connection pools, keyed by connection URI.
+    pools: Mutex<HashMap<String, Pool>>,
+}
+
+impl std::fmt::Debug for MysqlProx {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MysqlProx").finish_non_exhaustive()
+    }
+}
+
+impl MysqlProx {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Disconnect all connection pools.
+    ///
+    /// Waits until all connections are returned to their pool.
+    pub async fn close(&self) {
+        let pools = std::mem::take(&mut *self.pools.lock().unwrap());
+        for pool in pools.into_values() {
+            if let Err(err) = pool.disconnect().await {
+                tracing::warn!("Could not disconnect MySQL pool: {}", err);
+            }
+        }
+    }
+
+    /// Disconnect the connection pool of a database, like [`Self::close`].
+    pub async fn close_pool(&self, uri: &str) {
+        let Some(pool) = self.pools.lock().unwrap().remove(uri) else {
+            return;
+        };
+        if let Err(err) = pool.disconnect().await {
+            tracing::warn!("Could not disconnect MySQL pool: {}", err);
+        }
+    }
+
+    fn pool(&self, uri: &str) -> Result<Pool, anyhow::Error> {
+        let mut pools = self.pools.lock().unwrap();
+        if let Some(pool) = pools.get(uri) {
+            return Ok(pool.clone());
+        }
+        let pool = Pool::from_url(uri)?;
+        pools.insert(uri.to_string(), pool.clone());
+        Ok(pool)
+    }
+
+    async fn query_rows(&self, query: &SqlQuery) -> Result<(Vec<Column>, Vec<Row>), anyhow::Error> {
+        if query.fetch_cursors {
+            return Err(
+                ArgumentError("fetch_cursors is not supported for MySQL".to_string()).into(),
+            );
+        }
+        if query.limit.is_some() || query.offset.is_some() {
+            return Err(
+                ArgumentError("limit and offset are not supported for MySQL".to_string()).into(),
+            );
+        }
+        query.check_statement_count()?;
+
+        let params = match &query.args {
+            Some(args) if !args.is_empty() => {
+                Params::Positional(args.iter().map(json_to_value).collect())
+            }
+            _ => Params::Empty,
+        };
+
+        let limit = query.collect_limit();
+        let mut conn = self.pool(&query.db)?.get_conn().await?;
+        if query.read_only_tx {
+            let mut opts = TxOpts::default();
+            opts.with_readonly(true);
+            let mut tx = conn.start_transaction(opts).await?;
+            let res = exec(&mut tx, &query.query, params, limit).await?;
+            tx.commit().await?;
+            Ok(res)
+        } else {
+            exec(&mut conn, &query.query, params, limit).await
+        }
+    }
+}
+
+/// Run the statement, collecting at most `limit` rows.
+async fn exec<Q: Queryable>(
+    conn: &mut Q,
+    sql: &str,
+    params: Params,
+    limit: usize,
+) -> Result<(Vec<Column>, Vec<Row>), anyhow::Error> {
+    let mut result = conn.exec_iter(sql, params).await?;
+    let columns = result.columns().map(|c| c.to_vec()).unwrap_or_default();
+    let mut rows = Vec::new();
+    while rows.len() < limit {
+        let Some(row) = result.next().await? else {
+            break;
+        };
+        rows.push(row);
+    }
+    // Remaining rows must be read before the connection can be reused.
+    result.drop_result().await?;
+    Ok((columns, rows))
+}
+
+#[async_trait]
+impl SqlBackend for MysqlProx {
+    async fn query_json_maps(&self, query: SqlQuery) -> Result<Vec<JsonValue>, anyhow::Error> {
+        let (columns, rows) = self.query_rows(&query).await?;
+        rows.iter()
+            .map(|row| {
+                let mut map = serde_json::Map::new();
+                for (index, col) in columns.iter().enumerate() {
+                    let value = row_column_to_json(row, col, index)?;
+                    if query.omit_nulls && value.is_null() {
+                        continue;
+                    }
+                    map.insert(col.name_str().into_owned(), value);
+                }
+                Ok(JsonValue::Object(map))
+            })
+            .collect()
+    }
+
+    async fn query_column_arrays(
+        &self,
+        query: SqlQuery,
+    ) -> Result<(ColumnNames, Vec<Vec<JsonValue>>), anyhow::Error> {
+        let (columns, rows) = self.query_rows(&query).await?;
+
+        // Match the Postgres backend, which only knows the names if there
+        // are rows.
+        let names = if rows.is_empty() {
+            vec![]
+        } else {
+            columns.iter().map(|c| c.name_str().into_owned()).collect()
+        };
+        let arrays = rows
+            .iter()
+            .map(|row| row_to_json_columns(row, &columns))
+            .collect::<Result<_, _>>()?;
+        Ok((names, arrays))
+    }
+
+    async fn query_typed_columns(
+        &self,
+        query: SqlQuery,
+    ) -> Result<(Vec<ColumnInfo>, Vec<Vec<JsonValue>>), anyhow::Error> {
+        let (columns, rows) = self.query_rows(&query).await?;
+
+        let infos = columns
+            .iter()
+            .map(|c| ColumnInfo {
+                name: c.name_str().into_owned(),
+                type_: column_type(c),
+            })
+            .collect();
+        let arrays = rows
+            .iter()
+            .map(|row| row_to_json_columns(row, &columns))
+            .collect::<Result<_, _>>()?;
+        Ok((infos, arrays))
+    }
+}
+
+/// Convert a JSON argument to a MySQL value.
+///
+/// Nested arrays and objects are sent as JSON text.
+fn json_to_value(value: &JsonValue) -> Value {
+    match value {
+        JsonValue::Null => Value::NULL,
+        JsonValue::Bool(v) => Value::Int(*v as i64),
+        JsonValue::Number(n) => {
+            if let Some(v) = n.as_i64() {
+                Value::Int(v)
+            } else if let Some(v) = n.as_u64() {
+                Value::UInt(v)
+            } else {
+                Value::Double(n.as_f64().unwrap_or_default())
+            }
+        }
+        JsonValue::String(s) => Value::Bytes(s.as_bytes().to_vec()),
+        JsonValue::Array(_) | JsonValue::Object(_) => Value::Bytes(value.to_string().into_bytes()),
+    }
+}
+
+fn row_to_json_columns(row: &Row, columns: &[Column]) -> Result<Vec<JsonValue>, anyhow::Error> {
+    columns
+        .iter()
+        .enumerate()
+        .map(|(index, col)| row_column_to_json(row, col, index))
+        .collect()
+}
+
+fn is_binary(column: &Column) -> bool {
+    column.character_set() == BINARY_CHARSET
+}
+
+fn row_column_to_json(
+    row: &Row,
+    column: &Column,
+    index: usize,
+) -> Result<JsonValue, anyhow::Error> {
+    let Some(value) = row.as_ref(index) else {
+        bail!(
+            "Column '{}' was already taken from the row",
+            column.name_str()
+        );
+    };
+
+    let json = match value {
+        Value::NULL => JsonValue::Null,
+        Value::Int(v) => JsonValue::from(*v),
+        Value::UInt(v) => JsonValue::from(*v),
+        Value::Float(v) => serde_json::Number::from_f64(f64::from(*v))
+            .map(JsonValue::Number)
+            .unwrap_or(JsonValue::Null),
+        Value::Double(v) => serde_json::Number::from_f64(*v)
+            .map(JsonValue::Number)
+            .unwrap_or(JsonValue::Null),
+        Value::Date(year, month, day, hour, minute, second, micros) => {
+            if column.column_type() == MysqlType::MYSQL_TYPE_DATE {
+                JsonValue::String(format!("{year:04}-{month:02}-{day:02}"))
+            } else {
+                let mut s =
+                    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}");
+                if *micros > 0 {
+                    s.push_str(&format!(".{micros:06}"));
+                }
+                JsonValue::String(s)
+            }
+        }
+        Value::Time(negative, days, hours, minutes, seconds, micros) => {
+            let sign = if *negative { "-" } else { "" };
+            let hours = *days * 24 + u32::from(*hours);
+            let mut s = format!("{sign}{hours:02}:{minutes:02}:{seconds:02}");
+            if *micros > 0 {
+                s.push_str(&format!(".{micros:06}"));
+            }
+            JsonValue::String(s)
+        }
+        Value::Bytes(bytes) => match column.column_type() {
+            MysqlType::MYSQL_TYPE_JSON => serde_json::from_slice(bytes)?,
+            MysqlType::MYSQL_TYPE_BIT => {
+                JsonValue::String(base64::engine::general_purpose::STANDARD.encode(bytes))
+            }
+            _ if is_binary(column) => {
+                JsonValue::String(base64::engine::general_purpose::STANDARD.encode(bytes))
+            }
+            _ => match std::str::from_utf8(bytes) {
+                Ok(s) => JsonValue::String(s.to_string()),
+                Err(_) => bail!(
+                    "Could not convert column '{}' to json - invalid UTF-8",
+                    column.name_str()
+                ),
+            },
+        },
+    };
+    Ok(json)
+}
+
+/// Map a MySQL column type to the backend-neutral column type.
+///
+/// Must stay in sync with the values produced by [`row_column_to_json`].
+fn column_type(column: &Column) -> ColumnType {
+    let unsigned = column.flags().contains(ColumnFlags::UNSIGNED_FLAG);
+    match column.column_type() {
+        MysqlType::MYSQL_TYPE_TINY | MysqlType::MYSQL_TYPE_YEAR => ColumnType::Int16,
+        MysqlType::MYSQL_TYPE_SHORT if unsigned => ColumnType::Int32,
+        MysqlType::MYSQL_TYPE_SHORT => ColumnType::Int16,
+        MysqlType::MYSQL_TYPE_LONG if unsigned => ColumnType::Int64,
+        MysqlType::MYSQL_TYPE_INT24 | MysqlType::MYSQL_TYPE_LONG => ColumnType::Int32,
+        // Unsigned values above `i64::MAX` are still returned as numbers.
+        MysqlType::MYSQL_TYPE_LONGLONG => ColumnType::Int64,
+        MysqlType::MYSQL_TYPE_FLOAT => ColumnType::Float32,
+        MysqlType::MYSQL_TYPE_DOUBLE => ColumnType::Float64,
+        MysqlType::MYSQL_TYPE_JSON | MysqlType::MYSQL_TYPE_NULL => ColumnType::Json,
+        _ => ColumnType::Text,
+    }
+}
@@ -0,0 +1,366 @@
+//! Binding of JSON query arguments to statement parameters.
+
+use std::{borrow::Cow, error::Error};
+
+use bytes::BytesMut;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use daprox_core::{
+    lexer::{self, TokenKind},
+    ArgumentError, SqlQuery,
+};
+use postgres_types::{to_sql_checked, IsNull, ToSql, Type};
+use serde_json::{Number, Value as JsonValue};
+use tokio_postgres::Statement;
+
+use crate::{named::rewrite_named_params, numeric::Numeric, statements::unspecified_type};
+
+/// Unit of epoch timestamps sent as JSON numbers.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EpochUnit {
+    Seconds,
+    Milliseconds,
+}
+
+impl Default for EpochUnit {
+    fn default() -> Self {
+        Self::Milliseconds
+    }
+}
+
+/// Names of the types that arguments can be explicitly typed as.
+const ARG_TYPES: &[(&str, Type)] = &[
+    ("bool", Type::BOOL),
+    ("boolean", Type::BOOL),
+    ("int2", Type::INT2),
+    ("smallint", Type::INT2),
+    ("int4", Type::INT4),
+    ("int", Type::INT4),
+    ("integer", Type::INT4),
+    ("int8", Type::INT8),
+    ("bigint", Type::INT8),
+    ("float4", Type::FLOAT4),
+    ("real", Type::FLOAT4),
+    ("float8", Type::FLOAT8),
+    ("double precision", Type::FLOAT8),
+    ("numeric", Type::NUMERIC),
+    ("decimal", Type::NUMERIC),
+    ("text", Type::TEXT),
+    ("varchar", Type::VARCHAR),
+    ("uuid", Type::UUID),
+    ("json", Type::JSON),
+    ("jsonb", Type::JSONB),
+    ("date", Type::DATE),
+    ("timestamp", Type::TIMESTAMP),
+    ("timestamptz", Type::TIMESTAMPTZ),
+];
+
+/// A JSON argument, converted to the parameter type inferred by Postgres.
+#[derive(Debug)]
+pub(crate) struct JsonArg<'a> {
+    value: Cow<'a, JsonValue>,
+    /// Explicit type of the parameter, instead of the inferred one.
+    ty: Option<Type>,
+    epoch_unit: EpochUnit,
+}
+
+impl<'a> JsonArg<'a> {
+    /// Plain JSON values are bound as the type Postgres infers for the
+    /// parameter. Objects like `{"type": "uuid", "value": "..."}` with
+    /// exactly these two keys are bound as the given type instead.
+    pub fn new(value: &'a JsonValue, epoch_unit: EpochUnit) -> Result<Self, ArgumentError> {
+        let typed = value
+            .as_object()
+            .filter(|object| object.len() == 2)
+            .and_then(|object| Some((object.get("type")?, object.get("value")?)));
+        let Some((name, value)) = typed else {
+            return Ok(Self {
+                value: Cow::Borrowed(value),
+                ty: None,
+                epoch_unit,
+            });
+        };
+
+        let ty = name
+            .as_str()
+            .and_then(|name| ARG_TYPES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)))
+            .map(|(_, ty)| ty.clone())
+            .ok_or_else(|| {
+                let names = ARG_TYPES.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+                ArgumentError(format!(
+                    "unsupported argument type {name}, expected one of {}",
+                    names.join(", ")
+                ))
+            })?;
+        Ok(Self {
+            value: Cow::Borrowed(value),
+            ty: Some(ty),
+            epoch_unit,
+        })
+    }
+
+    fn owned(value: JsonValue, epoch_unit: EpochUnit) -> Self {
+        Self {
+            value: Cow::Owned(value),
+            ty: None,
+            epoch_unit,
+        }
+    }
+}
+
+/// Query text and the arguments to bind to its parameters.
+#[derive(Debug)]
+pub(crate) struct QueryArgs<'a> {
+    pub sql: Cow<'a, str>,
+    pub args: Vec<JsonArg<'a>>,
+}
+
+impl<'a> QueryArgs<'a> {
+    /// Collect the arguments of a query.
+    ///
+    /// With `kw_args`, `:name` placeholders in the query are rewritten to
+    /// positional parameters, bound to the values of the same name.
+    pub fn new(query: &'a SqlQuery, epoch_unit: EpochUnit) -> Result<Self, ArgumentError> {
+        let mut query_args = Self::positional(query, epoch_unit)?;
+
+        // The limit and offset are bound as parameters after the arguments.
+        let mut param = |value: u64| {
+            query_args
+                .args
+                .push(JsonArg::owned(JsonValue::from(value), epoch_unit));
+            format!("${}", query_args.args.len())
+        };
+        let limit = query.limit.map(&mut param);
+        let offset = query.offset.map(&mut param);
+        if limit.is_some() || offset.is_some() {
+            query_args.sql = Cow::Owned(append_limit(&query_args.sql, limit, offset)?);
+        }
+        Ok(query_args)
+    }
+
+    /// Types to prepare the statement with.
+    ///
+    /// Parameters of arguments without an explicit type are left for
+    /// Postgres to infer.
+    pub fn param_types(&self) -> Vec<Type> {
+        let len = self
+            .args
+            .iter()
+            .rposition(|arg| arg.ty.is_some())
+            .map_or(0, |index| index + 1);
+        self.args[..len]
+            .iter()
+            .map(|arg| arg.ty.clone().unwrap_or_else(unspecified_type))
+            .collect()
+    }
+
+    fn positional(query: &'a SqlQuery, epoch_unit: EpochUnit) -> Result<Self, ArgumentError> {
+        let Some(kw_args) = &query.kw_args else {
+            let args = query
+                .args
+                .iter()
+                .flatten()
+                .map(|value| JsonArg::new(value, epoch_unit))
+                .collect::<Result<_, _>>()?;
+            return Ok(Self {
+                sql: Cow::Borrowed(&query.query),
+                args,
+            });
+        };
+
+        if matches!(&query.args, Some(args) if !args.is_empty()) {
+            return Err(ArgumentError(
+                "args and kw_args can not be used together".to_string(),
+            ));
+        }
+
+        let (sql, names) = rewrite_named_params(&query.query);
+        let args = names
+            .into_iter()
+            .map(|name| match kw_args.get(name) {
+                Some(value) => JsonArg::new(value, epoch_unit),
+                None => Err(ArgumentError(format!(
+                    "query references parameter :{name}, which is missing from kw_args"
+                ))),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            sql: Cow::Owned(sql),
+            args,
+        })
+    }
+}
+
+/// Append `LIMIT` and `OFFSET` clauses to the query.
+///
+/// The values are SQL expressions, like parameters or literals.
+/// Queries that already limit their rows on the top level are rejected,
+/// since it would be unclear which limit applies.
+pub(crate) fn append_limit(
+    sql: &str,
+    limit: Option<String>,
+    offset: Option<String>,
+) -> Result<String, ArgumentError> {
+    let tokens = lexer::tokens(sql);
+    let mut depth = 0usize;
+    for token in &tokens {
+        match (token.kind, token.text) {
+            (TokenKind::Other, "(") => depth += 1,
+            (TokenKind::Other, ")") => depth = depth.saturating_sub(1),
+            (TokenKind::Word, word)
+                if depth == 0
+                    && ["limit", "offset", "fetch"]
+                        .iter()
+                        .any(|kw| word.eq_ignore_ascii_case(kw)) =>
+            {
+                return Err(ArgumentError(format!(
+                    "query already contains a {} clause, which can not be combined with \
+                     limit and offset",
+                    word.to_uppercase()
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    // Drop a trailing semicolon, so the clauses are part of the statement.
+    let end = match tokens.last() {
+        Some(token) if token.kind == TokenKind::Semicolon => {
+            token.text.as_ptr() as usize - sql.as_ptr() as usize
+        }
+        _ => sql.len(),
+    };
+    // Start on a new line, in case the query ends with a line comment.
+    let mut out = sql[..end].to_string();
+    if let Some(limit) = limit {
+        out.push_str(&format!("\nLIMIT {limit}"));
+    }
+    if let Some(offset) = offset {
+        out.push_str(&format!("\nOFFSET {offset}"));
+    }
+    Ok(out)
+}
+
+type BoxError = Box<dyn Error + Sync + Send>;
+
+impl<'a> ToSql for JsonArg<'a> {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxError> {
+        match (&*self.value, ty) {
+            (JsonValue::Null, _) => Ok(IsNull::Yes),
+            (value, &Type::JSON | &Type::JSONB) => value.to_sql(ty, out),
+            (JsonValue::Bool(v), &Type::BOOL) => v.to_sql(ty, out),
+            (JsonValue::Number(n), &Type::INT2) => i16::try_from(integer(n)?)
+                .map_err(|_| out_of_range(n, ty))?
+                .to_sql(ty, out),
+            (JsonValue::Number(n), &Type::INT4) => i32::try_from(integer(n)?)
+                .map_err(|_| out_of_range(n, ty))?
+                .to_sql(ty, out),
+            (JsonValue::Number(n), &Type::INT8) => integer(n)?.to_sql(ty, out),
+            (JsonValue::Number(n), &Type::FLOAT4) => (float(n)? as f32).to_sql(ty, out),
+            (JsonValue::Number(n), &Type::FLOAT8) => float(n)?.to_sql(ty, out),
+            // Numbers keep their exact decimal text, strings are parsed the
+            // same way, so values beyond `f64` precision can be bound.
+            (JsonValue::Number(n), &Type::NUMERIC) => {
+                Numeric::parse(&n.to_string())?.to_sql(ty, out)
+            }
+            (JsonValue::String(s), &Type::NUMERIC) => Numeric::parse(s)?.to_sql(ty, out),
+            (JsonValue::Number(n), &Type::TIMESTAMPTZ) => self.epoch(n)?.to_sql(ty, out),
+            (JsonValue::Number(n), &Type::TIMESTAMP) => self.epoch(n)?.naive_utc().to_sql(ty, out),
+            (JsonValue::Number(n), &Type::DATE) => self.epoch(n)?.date_naive().to_sql(ty, out),
+            (JsonValue::String(s), &Type::TIMESTAMPTZ) => DateTime::parse_from_rfc3339(s)?
+                .with_timezone(&Utc)
+                .to_sql(ty, out),
+            (JsonValue::String(s), &Type::TIMESTAMP) => parse_naive_datetime(s)?.to_sql(ty, out),
+            (JsonValue::String(s), &Type::DATE) => {
+                NaiveDate::parse_from_str(s, "%Y-%m-%d")?.to_sql(ty, out)
+            }
+            (JsonValue::String(s), &Type::TEXT | &Type::VARCHAR | &Type::BPCHAR | &Type::NAME) => {
+                s.to_sql(ty, out)
+            }
+            (JsonValue::String(s), &Type::UUID) => uuid::Uuid::parse_str(s)?.to_sql(ty, out),
+            (value, ty) => Err(format!("can not bind JSON value {value} to type {ty}").into()),
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> JsonArg<'a> {
+    /// Interpret a number as an epoch timestamp.
+    fn epoch(&self, n: &Number) -> Result<DateTime<Utc>, BoxError> {
+        let value = integer(n)?;
+        let timestamp = match self.epoch_unit {
+            EpochUnit::Seconds => Utc.timestamp_opt(value, 0).single(),
+            EpochUnit::Milliseconds => Utc.timestamp_millis_opt(value).single(),
+        };
+        timestamp.ok_or_else(|| format!("epoch timestamp {value} is out of range").into())
+    }
+}
+
+fn integer(n: &Number) -> Result<i64, BoxError> {
+    n.as_i64()
+        .ok_or_else(|| format!("expected an integer, got {n}").into())
+}
+
+fn out_of_range(n: &Number, ty: &Type) -> BoxError {
+    format!("value {n} is out of range for type {ty}").into()
+}
+
+fn float(n: &Number) -> Result<f64, BoxError> {
+    n.as_f64()
+        .ok_or_else(|| format!("expected a number, got {n}").into())
+}
+
+/// Parse a timestamp without timezone.
+///
+/// RFC3339 strings with an offset are converted to UTC.
+fn parse_naive_datetime(s: &str) -> Result<NaiveDateTime, BoxError> {
+    if let Ok(ts) = DateTime::parse_from_rfc3339(s) {
+        return Ok(ts.naive_utc());
+    }
+    let ts = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f"))?;
+    Ok(ts)
+}
+
+/// Select the arguments to bind to a prepared statement.
+///
+/// Arguments are encoded up front, so invalid values are reported as an
+/// [`ArgumentError`] instead of failing while the query is sent.
+/// Arguments for queries without parameters are ignored, unless `strict` is
+/// set.
+pub(crate) fn statement_params<'a>(
+    statement: &Statement,
+    args: &'a [JsonArg<'a>],
+    strict: bool,
+) -> Result<Vec<&'a (dyn ToSql + Sync)>, ArgumentError> {
+    let expected = statement.params();
+    if expected.is_empty() && !args.is_empty() {
+        if strict {
+            return Err(ArgumentError(
+                "query has no parameters but args were provided".to_string(),
+            ));
+        }
+        return Ok(Vec::new());
+    }
+    if args.len() != expected.len() {
+        return Err(ArgumentError(format!(
+            "query expects {} arguments, but {} were provided",
+            expected.len(),
+            args.len()
+        )));
+    }
+
+    let mut buf = BytesMut::new();
+    for (index, (arg, ty)) in args.iter().zip(expected).enumerate() {
+        buf.clear();
+        arg.to_sql_checked(ty, &mut buf)
+            .map_err(|err| ArgumentError(format!("invalid argument ${}: {err}", index + 1)))?;
+    }
+
+    Ok(args.iter().map(|arg| arg as &(dyn ToSql + Sync)).collect())
+}
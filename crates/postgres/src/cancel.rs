@@ -0,0 +1,94 @@
+//! Cancellation of running queries.
+//!
+//! Postgres cancels queries through a separate connection, which uses the
+//! same TLS settings as the connection of the query.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use tokio_postgres::{CancelToken, NoTls};
+
+/// Counter of the queries that were cancelled because their request was
+/// dropped.
+const CANCELLED_QUERIES_COUNTER: &str = "daprox_queries_cancelled_total";
+
+/// Sends cancel requests for the queries of a connection.
+#[derive(Clone)]
+pub(crate) struct Canceller {
+    token: CancelToken,
+    #[cfg(feature = "rustls")]
+    tls: Option<tokio_postgres_rustls::MakeRustlsConnect>,
+}
+
+impl Canceller {
+    pub fn insecure(token: CancelToken) -> Self {
+        Self {
+            token,
+            #[cfg(feature = "rustls")]
+            tls: None,
+        }
+    }
+
+    #[cfg(feature = "rustls")]
+    pub fn rustls(token: CancelToken, tls: tokio_postgres_rustls::MakeRustlsConnect) -> Self {
+        Self {
+            token,
+            tls: Some(tls),
+        }
+    }
+
+    async fn cancel(self) -> Result<(), tokio_postgres::Error> {
+        #[cfg(feature = "rustls")]
+        if let Some(tls) = self.tls {
+            return self.token.cancel_query(tls).await;
+        }
+        self.token.cancel_query(NoTls).await
+    }
+}
+
+/// Cancels the query running on a connection if dropped before
+/// [`CancelGuard::disarm`] is called.
+///
+/// Futures are dropped while a query is running if the client of the request
+/// disconnected or the request timed out.
+/// The connection is then closed instead of returned to the pool, so the
+/// cancel request can not hit a later query.
+pub(crate) struct CancelGuard {
+    canceller: Option<Canceller>,
+    discard: Arc<AtomicBool>,
+}
+
+impl CancelGuard {
+    pub fn new(canceller: Canceller, discard: Arc<AtomicBool>) -> Self {
+        Self {
+            canceller: Some(canceller),
+            discard,
+        }
+    }
+
+    /// The query finished, so there is nothing to cancel.
+    pub fn disarm(mut self) {
+        self.canceller = None;
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        let Some(canceller) = self.canceller.take() else {
+            return;
+        };
+        self.discard.store(true, Ordering::Relaxed);
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        tracing::debug!("Cancelling abandoned query");
+        metrics::increment_counter!(CANCELLED_QUERIES_COUNTER);
+        runtime.spawn(async move {
+            if let Err(err) = canceller.cancel().await {
+                tracing::warn!("Could not cancel query: {}", err);
+            }
+        });
+    }
+}
@@ -0,0 +1,76 @@
+//! Decoding of Postgres composite and anonymous `record` values.
+
+use std::error::Error;
+
+use postgres_types::{FromSql, Kind, Type};
+
+/// A single field of a composite value.
+pub(crate) struct Field<'a> {
+    /// Type of the field, if known.
+    ///
+    /// Fields of anonymous records only carry a type OID, so only built-in
+    /// types are known.
+    pub type_: Option<Type>,
+    pub oid: u32,
+    pub raw: Option<&'a [u8]>,
+}
+
+/// The undecoded fields of a composite value, in declaration order.
+pub(crate) struct Fields<'a>(pub Vec<Field<'a>>);
+
+impl<'a> FromSql<'a> for Fields<'a> {
+    fn from_sql(ty: &Type, mut raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let declared = match ty.kind() {
+            Kind::Composite(fields) => Some(fields),
+            _ => None,
+        };
+
+        let count = read_i32(&mut raw)?;
+        let count = usize::try_from(count).map_err(|_| "invalid composite field count")?;
+        if let Some(declared) = declared {
+            if declared.len() != count {
+                return Err("composite field count does not match its type".into());
+            }
+        }
+
+        let mut fields = Vec::with_capacity(count);
+        for index in 0..count {
+            let oid = read_i32(&mut raw)? as u32;
+            let len = read_i32(&mut raw)?;
+            let value = if len < 0 {
+                None
+            } else {
+                let len = len as usize;
+                if raw.len() < len {
+                    return Err("invalid composite value: truncated field".into());
+                }
+                let (value, rest) = raw.split_at(len);
+                raw = rest;
+                Some(value)
+            };
+            let type_ = match declared {
+                Some(declared) => Some(declared[index].type_().clone()),
+                None => Type::from_oid(oid),
+            };
+            fields.push(Field {
+                type_,
+                oid,
+                raw: value,
+            });
+        }
+        Ok(Self(fields))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(ty.kind(), Kind::Composite(_)) || *ty == Type::RECORD
+    }
+}
+
+fn read_i32(buf: &mut &[u8]) -> Result<i32, Box<dyn Error + Sync + Send>> {
+    if buf.len() < 4 {
+        return Err("invalid composite value: truncated header".into());
+    }
+    let (value, rest) = buf.split_at(4);
+    *buf = rest;
+    Ok(i32::from_be_bytes(value.try_into().unwrap()))
+}
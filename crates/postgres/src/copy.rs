@@ -0,0 +1,218 @@
+//! Bulk data transfer with the COPY protocol.
+
+use std::pin::Pin;
+
+use bytes::Bytes;
+use daprox_core::{
+    lexer::{self, TokenKind},
+    ArgumentError, SqlQuery,
+};
+use futures::{SinkExt, Stream, StreamExt};
+use tokio_postgres::CopyOutStream;
+
+use crate::{
+    cancel::CancelGuard, cancel_on_drop, database_error, pool::PooledConnection, quote_ident,
+    PostgresProx,
+};
+
+/// Keywords that can start the query of `COPY (query) TO`.
+const COPY_QUERY_KEYWORDS: &[&str] = &["SELECT", "WITH", "VALUES", "TABLE"];
+
+/// A running `COPY TO`, along with the connection it runs on.
+struct CopyOut {
+    conn: PooledConnection,
+    guard: CancelGuard,
+    stream: Pin<Box<CopyOutStream>>,
+    read_only_tx: bool,
+}
+
+/// Check that the query can be embedded in `COPY (query) TO STDOUT`.
+///
+/// The query is pasted into the COPY command, so it must be a single query
+/// that can not close the parentheses, like with
+/// `SELECT 1) TO PROGRAM '...' --`.
+fn check_copy_query(sql: &str) -> Result<(), ArgumentError> {
+    let tokens = lexer::tokens_with_comments(sql);
+    let is_query = tokens.first().map_or(false, |token| {
+        token.kind == TokenKind::Word
+            && COPY_QUERY_KEYWORDS.contains(&token.text.to_ascii_uppercase().as_str())
+    });
+    if !is_query {
+        return Err(ArgumentError(
+            "COPY only supports a single SELECT, WITH, VALUES or TABLE query".to_string(),
+        ));
+    }
+
+    let mut depth = 0usize;
+    for token in &tokens {
+        match (token.kind, token.text) {
+            (TokenKind::Semicolon, _) => {
+                return Err(ArgumentError(
+                    "COPY only supports a single statement".to_string(),
+                ));
+            }
+            (TokenKind::Comment, _) => {
+                return Err(ArgumentError(
+                    "comments are not allowed in COPY queries".to_string(),
+                ));
+            }
+            (TokenKind::Other, "(") => depth += 1,
+            (TokenKind::Other, ")") => {
+                depth = depth.checked_sub(1).ok_or_else(|| {
+                    ArgumentError("unbalanced parentheses in COPY query".to_string())
+                })?;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(ArgumentError(
+            "unbalanced parentheses in COPY query".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Data formats supported by COPY.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CopyFormat {
+    Csv,
+    /// The Postgres binary COPY format.
+    Binary,
+}
+
+impl CopyFormat {
+    /// Content type of the raw COPY data.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Binary => "application/octet-stream",
+        }
+    }
+
+    fn option(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Binary => "binary",
+        }
+    }
+}
+
+impl Default for CopyFormat {
+    fn default() -> Self {
+        Self::Csv
+    }
+}
+
+impl PostgresProx {
+    /// Run a query with `COPY (query) TO STDOUT` and stream the raw output.
+    ///
+    /// `header` adds a header row with the column names to CSV output.
+    /// With `read_only_tx`, the COPY runs in a read-only transaction.
+    pub async fn copy_out(
+        &self,
+        query: &SqlQuery,
+        format: CopyFormat,
+        header: bool,
+    ) -> Result<impl Stream<Item = Result<Bytes, anyhow::Error>> + Send + 'static, anyhow::Error>
+    {
+        let sql = query.query.trim().trim_end_matches(';');
+        check_copy_query(sql)?;
+        let mut options = format!("FORMAT {}", format.option());
+        if header && format == CopyFormat::Csv {
+            options.push_str(", HEADER");
+        }
+        let copy = format!("COPY ({sql}) TO STDOUT ({options})");
+
+        let conn = self.query_connection(query).await?;
+        // Cancels the COPY and discards the connection, which may still be
+        // in the transaction, unless all data was read.
+        let guard = conn.cancel_guard();
+        let read_only_tx = query.read_only_tx;
+        let stream = cancel_on_drop(conn.cancel_guard(), async {
+            if read_only_tx {
+                conn.client
+                    .batch_execute("BEGIN READ ONLY")
+                    .await
+                    .map_err(database_error)?;
+            }
+            conn.client.copy_out(&copy).await.map_err(database_error)
+        })
+        .await?;
+
+        let state = CopyOut {
+            conn,
+            guard,
+            stream: Box::pin(stream),
+            read_only_tx,
+        };
+        Ok(futures::stream::unfold(Some(state), |state| async move {
+            let mut state = state?;
+            match state.stream.next().await {
+                Some(Ok(chunk)) => Some((Ok(chunk), Some(state))),
+                Some(Err(err)) => Some((Err(database_error(err)), None)),
+                None => {
+                    if state.read_only_tx {
+                        if let Err(err) = state.conn.client.batch_execute("COMMIT").await {
+                            return Some((Err(database_error(err)), None));
+                        }
+                    }
+                    state.guard.disarm();
+                    None
+                }
+            }
+        }))
+    }
+
+    /// Load data into a table with `COPY table FROM STDIN`.
+    ///
+    /// `table` may be schema-qualified. If `columns` is empty, the data must
+    /// contain all columns of the table.
+    /// Returns the number of loaded rows.
+    pub async fn copy_in<S>(
+        &self,
+        db: &str,
+        table: &str,
+        columns: &[String],
+        format: CopyFormat,
+        header: bool,
+        data: S,
+    ) -> Result<u64, anyhow::Error>
+    where
+        S: Stream<Item = Result<Bytes, anyhow::Error>>,
+    {
+        let table = table
+            .split('.')
+            .map(quote_ident)
+            .collect::<Vec<_>>()
+            .join(".");
+        let columns = if columns.is_empty() {
+            String::new()
+        } else {
+            let names = columns.iter().map(|c| quote_ident(c)).collect::<Vec<_>>();
+            format!(" ({})", names.join(", "))
+        };
+        let mut options = format!("FORMAT {}", format.option());
+        if header && format == CopyFormat::Csv {
+            options.push_str(", HEADER");
+        }
+        let copy = format!("COPY {table}{columns} FROM STDIN ({options})");
+
+        let conn = self.connection(db).await?;
+        let sink = conn
+            .client
+            .copy_in::<_, Bytes>(&copy)
+            .await
+            .map_err(database_error)?;
+        futures::pin_mut!(sink);
+        futures::pin_mut!(data);
+
+        // Returning early drops the sink, which aborts the COPY.
+        while let Some(chunk) = data.next().await {
+            sink.send(chunk?).await.map_err(database_error)?;
+        }
+        let rows = sink.as_mut().finish().await.map_err(database_error)?;
+        Ok(rows)
+    }
+}
@@ -0,0 +1,143 @@
+//! JSON representation of date and time values.
+
+use std::{error::Error, fmt::Write as _};
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, Utc};
+use daprox_core::ArgumentError;
+use postgres_types::{FromSql, Type};
+
+/// Parse a fixed timezone offset like `+02:00` or `-0530`.
+pub(crate) fn parse_offset(value: &str) -> Result<FixedOffset, ArgumentError> {
+    let invalid = || ArgumentError(format!("invalid timezone offset '{value}'"));
+
+    let (sign, rest) = match value.as_bytes().first() {
+        Some(b'+') => (1, &value[1..]),
+        Some(b'-') => (-1, &value[1..]),
+        _ => return Err(invalid()),
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some(parts) => parts,
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    if minutes >= 60 {
+        return Err(invalid());
+    }
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
+}
+
+/// Format a `timestamptz` as RFC3339.
+///
+/// Uses a `Z` suffix instead of `+00:00` with `utc_suffix`.
+pub(crate) fn format_timestamptz(value: DateTime<Utc>, utc_suffix: bool) -> String {
+    value.to_rfc3339_opts(SecondsFormat::AutoSi, utc_suffix)
+}
+
+/// Format a `timestamp` as RFC3339 without an offset.
+///
+/// With `assume_timezone`, the value is converted from that timezone to UTC
+/// and formatted like a `timestamptz`.
+pub(crate) fn format_timestamp(
+    value: NaiveDateTime,
+    assume_timezone: Option<FixedOffset>,
+) -> String {
+    match assume_timezone {
+        Some(offset) => {
+            let utc = DateTime::<Utc>::from_utc(value - offset, Utc);
+            format_timestamptz(utc, true)
+        }
+        None => value.format("%Y-%m-%dT%H:%M:%S%.f").to_string(),
+    }
+}
+
+/// Format a `date` as `YYYY-MM-DD`.
+pub(crate) fn format_date(value: NaiveDate) -> String {
+    value.format("%Y-%m-%d").to_string()
+}
+
+/// Format a `time` as `HH:MM:SS`, with fractional seconds if present.
+pub(crate) fn format_time(value: NaiveTime) -> String {
+    value.format("%H:%M:%S%.f").to_string()
+}
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+
+/// A Postgres `interval` value.
+///
+/// The components are kept separate, like Postgres does, since months and
+/// days have no fixed length.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct Interval {
+    pub months: i32,
+    pub days: i32,
+    pub microseconds: i64,
+}
+
+impl Interval {
+    /// Format as an ISO 8601 duration like `P1Y2M3DT4H5M6.5S`.
+    ///
+    /// Matches the `iso_8601` interval style of Postgres: each component
+    /// carries its own sign, and zero components are omitted.
+    pub fn to_iso8601(&self) -> String {
+        if self.months == 0 && self.days == 0 && self.microseconds == 0 {
+            return "PT0S".to_string();
+        }
+
+        let mut out = String::from("P");
+        let (years, months) = (self.months / 12, self.months % 12);
+        for (value, unit) in [(years, 'Y'), (months, 'M'), (self.days, 'D')] {
+            if value != 0 {
+                write!(out, "{value}{unit}").unwrap();
+            }
+        }
+        if self.microseconds == 0 {
+            return out;
+        }
+
+        out.push('T');
+        let seconds = self.microseconds / MICROS_PER_SECOND;
+        let fraction = self.microseconds % MICROS_PER_SECOND;
+        let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+        for (value, unit) in [(hours, 'H'), (minutes, 'M')] {
+            if value != 0 {
+                write!(out, "{value}{unit}").unwrap();
+            }
+        }
+        if fraction != 0 {
+            let sign = if seconds < 0 || fraction < 0 { "-" } else { "" };
+            let digits = format!("{:06}", fraction.abs());
+            write!(
+                out,
+                "{sign}{}.{}S",
+                seconds.abs(),
+                digits.trim_end_matches('0')
+            )
+            .unwrap();
+        } else if seconds != 0 {
+            write!(out, "{seconds}S").unwrap();
+        }
+        out
+    }
+}
+
+impl<'a> FromSql<'a> for Interval {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let raw: [u8; 16] = raw
+            .try_into()
+            .map_err(|_| "invalid interval value: expected 16 bytes")?;
+        let (microseconds, rest) = raw.split_at(8);
+        let (days, months) = rest.split_at(4);
+        Ok(Self {
+            months: i32::from_be_bytes(months.try_into().unwrap()),
+            days: i32::from_be_bytes(days.try_into().unwrap()),
+            microseconds: i64::from_be_bytes(microseconds.try_into().unwrap()),
+        })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty == &Type::INTERVAL
+    }
+}
@@ -0,0 +1,184 @@
+//! Describing query results without executing them.
+
+use daprox_core::{ColumnType, SqlQuery};
+use postgres_types::{Kind, Type};
+
+use crate::{column_type, database_error, PostgresProx};
+
+/// Whether a table column has a `NOT NULL` constraint.
+const NOT_NULL_QUERY: &str = "SELECT attnotnull FROM pg_catalog.pg_attribute \
+                              WHERE attrelid = $1 AND attnum = $2";
+
+impl PostgresProx {
+    /// Describe the result columns of a query without executing it.
+    pub async fn describe(
+        &self,
+        query: &SqlQuery,
+    ) -> Result<Vec<ColumnDescription>, anyhow::Error> {
+        let mut pooled = self.query_connection(query).await?;
+        let conn = &mut *pooled;
+        let untyped_as_text = self.config.untyped_args_as_text;
+        // Cached like the statements of executed queries, so describing a
+        // query also speeds up running it afterwards.
+        let statement = conn
+            .statements
+            .prepare(&conn.client, &query.query, &[], untyped_as_text)
+            .await
+            .map_err(database_error)?;
+
+        let mut columns = Vec::with_capacity(statement.columns().len());
+        for (index, col) in statement.columns().iter().enumerate() {
+            let nullable = match (col.table_oid(), col.column_id()) {
+                (Some(table), Some(attnum)) => {
+                    let not_null = conn
+                        .statements
+                        .prepare(&conn.client, NOT_NULL_QUERY, &[], untyped_as_text)
+                        .await?;
+                    let row = conn.client.query_opt(&not_null, &[&table, &attnum]).await?;
+                    match row {
+                        Some(row) if row.get::<_, bool>(0) => Nullability::NotNull,
+                        Some(_) => Nullability::Nullable,
+                        None => Nullability::Unknown,
+                    }
+                }
+                _ => Nullability::Unknown,
+            };
+
+            columns.push(ColumnDescription {
+                name: col.name().to_string(),
+                ordinal: index + 1,
+                type_name: col.type_().name().to_string(),
+                nullable,
+                type_: col.type_().clone(),
+            });
+        }
+
+        Ok(columns)
+    }
+
+    /// Prepare a query without executing it, to check that it is valid.
+    ///
+    /// Returns the types the server inferred for the parameters and result
+    /// columns.
+    pub async fn validate(&self, query: &SqlQuery) -> Result<QuerySignature, anyhow::Error> {
+        let mut pooled = self.query_connection(query).await?;
+        let conn = &mut *pooled;
+        let statement = conn
+            .statements
+            .prepare(
+                &conn.client,
+                &query.query,
+                &[],
+                self.config.untyped_args_as_text,
+            )
+            .await
+            .map_err(database_error)?;
+
+        Ok(QuerySignature {
+            params: statement
+                .params()
+                .iter()
+                .map(|ty| ty.name().to_string())
+                .collect(),
+            columns: statement
+                .columns()
+                .iter()
+                .map(|col| ColumnSignature {
+                    name: col.name().to_string(),
+                    type_name: col.type_().name().to_string(),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Parameter and result types of a query, as returned by
+/// [`PostgresProx::validate`].
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct QuerySignature {
+    /// The Postgres type names of the parameters, in order.
+    pub params: Vec<String>,
+    pub columns: Vec<ColumnSignature>,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct ColumnSignature {
+    pub name: String,
+    /// The Postgres type name.
+    pub type_name: String,
+}
+
+/// Metadata of a result column, as returned by [`PostgresProx::describe`].
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct ColumnDescription {
+    pub name: String,
+    /// 1-based position of the column in the result.
+    pub ordinal: usize,
+    /// The Postgres type name.
+    pub type_name: String,
+    pub nullable: Nullability,
+    #[serde(skip)]
+    type_: Type,
+}
+
+impl ColumnDescription {
+    /// The TypeScript type of the column values, as produced by the JSON
+    /// output formats.
+    pub fn typescript_type(&self) -> String {
+        let ty = typescript_type(&self.type_);
+        match self.nullable {
+            Nullability::NotNull => ty,
+            Nullability::Nullable | Nullability::Unknown => format!("{ty} | null"),
+        }
+    }
+}
+
+/// Whether a result column can contain NULL values.
+///
+/// Nullability is only known for columns that directly reference a table
+/// column, based on its `NOT NULL` constraint. Note that even those can be
+/// NULL when the table is on the nullable side of an outer join.
+#[derive(serde::Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Nullability {
+    Nullable,
+    NotNull,
+    /// Computed columns, like expressions and function results.
+    Unknown,
+}
+
+/// Map a Postgres type to the TypeScript type of its JSON representation.
+fn typescript_type(ty: &Type) -> String {
+    if let Kind::Array(inner) = ty.kind() {
+        return format!("Array<{} | null>", typescript_type(inner));
+    }
+
+    match column_type(ty) {
+        ColumnType::Bool => "boolean".to_string(),
+        ColumnType::Int16
+        | ColumnType::Int32
+        | ColumnType::Int64
+        | ColumnType::Float32
+        | ColumnType::Float64 => "number".to_string(),
+        ColumnType::Text | ColumnType::Timestamp | ColumnType::TimestampTz => "string".to_string(),
+        ColumnType::Json => "unknown".to_string(),
+    }
+}
+
+/// Render a TypeScript interface describing a single result row.
+pub fn typescript_interface(name: &str, columns: &[ColumnDescription]) -> String {
+    let mut out = format!("export interface {name} {{\n");
+    for col in columns {
+        let is_ident = col.name.chars().enumerate().all(|(i, c)| {
+            c == '_' || c == '$' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit())
+        });
+        let key = if is_ident && !col.name.is_empty() {
+            col.name.clone()
+        } else {
+            serde_json::to_string(&col.name).unwrap()
+        };
+        out.push_str(&format!("  {key}: {};\n", col.typescript_type()));
+    }
+    out.push_str("}\n");
+    out
+}
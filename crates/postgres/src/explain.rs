@@ -0,0 +1,65 @@
+//! Query plans via `EXPLAIN`.
+
+use daprox_core::{DatabaseError, DatabaseErrorKind, SqlQuery};
+use serde_json::Value as JsonValue;
+
+use crate::{
+    args::{statement_params, QueryArgs},
+    collect_rows, database_error, PostgresProx,
+};
+
+impl PostgresProx {
+    /// Get the plan of a query with `EXPLAIN (FORMAT JSON)`.
+    ///
+    /// With `analyze`, the query is executed to collect actual row counts
+    /// and timings, including any side effects of the query.
+    ///
+    /// Errors reported by the server are classified as invalid queries,
+    /// since they mostly come from planning the query.
+    pub async fn explain(
+        &self,
+        query: &SqlQuery,
+        analyze: bool,
+    ) -> Result<JsonValue, anyhow::Error> {
+        query.check_statement_count()?;
+        let mut pooled = self.query_connection(query).await?;
+        let conn = &mut *pooled;
+        let query_args = QueryArgs::new(query, self.config.epoch_args_unit)?;
+        let types = query_args.param_types();
+        let QueryArgs { sql, args } = query_args;
+        let options = if analyze {
+            "FORMAT JSON, ANALYZE"
+        } else {
+            "FORMAT JSON"
+        };
+        let sql = format!("EXPLAIN ({options}) {sql}");
+
+        let statement = conn
+            .statements
+            .prepare(&conn.client, &sql, &types, self.config.untyped_args_as_text)
+            .await
+            .map_err(plan_error)?;
+        let params = statement_params(&statement, &args, self.config.strict_args)?;
+        let rows = conn
+            .client
+            .query_raw(&statement, params)
+            .await
+            .map_err(plan_error)?;
+        let rows = collect_rows(rows, 1).await?;
+
+        match rows.first() {
+            Some(row) => Ok(row.try_get(0)?),
+            None => Ok(JsonValue::Null),
+        }
+    }
+}
+
+fn plan_error(err: tokio_postgres::Error) -> anyhow::Error {
+    let mut err = database_error(err);
+    if let Some(db) = err.downcast_mut::<DatabaseError>() {
+        if db.kind != DatabaseErrorKind::Unavailable {
+            db.kind = DatabaseErrorKind::InvalidQuery;
+        }
+    }
+    err
+}
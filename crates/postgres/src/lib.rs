@@ -1,19 +1,259 @@
-#![feature(async_fn_in_trait)]
+mod args;
+mod cancel;
+mod composite;
+mod copy;
+mod datetime;
+mod describe;
+mod explain;
+mod listen;
+mod named;
+mod network;
+mod numeric;
+mod pool;
+mod range;
+mod schema;
+mod statements;
+mod transaction;
 
-use std::sync::Arc;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    num::NonZeroUsize,
+    pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+};
 
-use anyhow::bail;
-use daprox_core::{ColumnNames, SqlBackend, SqlQuery};
-use postgres_types::{FromSql, ToSql, Type};
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use base64::Engine as _;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, Utc};
+use daprox_core::{
+    ArgumentError, ByteaEncoding, ColumnInfo, ColumnNames, ColumnType, DatabaseError,
+    DatabaseErrorKind, JsonEncoding, JsonRowStream, Notice, NoticeSink, QueryProtocol, SqlBackend,
+    SqlQuery,
+};
+use futures::{StreamExt as _, TryStreamExt as _};
+use lru::LruCache;
+use postgres_types::{FromSql, Kind, Type};
 use rustls::client::ServerCertVerifier;
 use serde_json::Value as JsonValue;
-use tokio::sync::Mutex;
-use tokio_postgres::{Client, Column, Row, RowStream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::Mutex,
+};
+use tokio_postgres::{
+    error::SqlState, AsyncMessage, Client, Column, Row, RowStream, SimpleQueryMessage,
+    SimpleQueryRow, Statement, Transaction,
+};
 use url::Url;
 
-pub struct PostgresProx(Arc<Mutex<State>>);
+pub use self::args::EpochUnit;
+pub use self::copy::CopyFormat;
+pub use self::describe::{
+    typescript_interface, ColumnDescription, ColumnSignature, Nullability, QuerySignature,
+};
+pub use self::listen::{Notification, NotificationOverflow, NotificationStream};
+pub use self::schema::{ColumnSchema, TableKind, TableSchema};
+pub use self::transaction::TransactionError;
+use self::{
+    args::{append_limit, statement_params, QueryArgs},
+    cancel::{CancelGuard, Canceller},
+    datetime::Interval,
+    listen::NotificationSender,
+    numeric::Numeric,
+    pool::{Pool, PooledConnection},
+    range::Range,
+    statements::StatementCache,
+};
 
-struct State {}
+/// Whether connections can use TLS, which requires the `rustls` feature.
+pub const TLS_SUPPORT: bool = cfg!(feature = "rustls");
+
+pub struct PostgresProx {
+    config: PostgresConfig,
+    state: Arc<Mutex<State>>,
+}
+
+impl std::fmt::Debug for PostgresProx {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PostgresProx")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+struct State {
+    /// Connection pools, keyed by connection URI.
+    pools: LruCache<String, Arc<Pool>>,
+}
+
+/// Configuration for the Postgres backend.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone, Debug)]
+#[serde(default)]
+pub struct PostgresConfig {
+    /// Bind string arguments whose parameter type Postgres can not infer as
+    /// `text`, and rely on implicit casts.
+    pub untyped_args_as_text: bool,
+    /// Maximum number of open connections per database.
+    pub pool_size: usize,
+    /// Maximum number of open connections of individual databases, keyed by
+    /// connection URI. Overrides `pool_size`.
+    #[serde(skip)]
+    pub pool_sizes: HashMap<String, usize>,
+    /// Time to wait for a connection when all connections to a database are
+    /// in use, before failing the query as unavailable.
+    /// Waits indefinitely if unset.
+    pub acquire_timeout_ms: Option<u64>,
+    /// Number of idle connections kept open and periodically validated
+    /// per database, to avoid connection latency after idle periods.
+    pub min_idle: usize,
+    /// Close connections that were idle for longer than this, before the
+    /// database or a firewall severs them. Replaced by new connections if
+    /// `min_idle` is set. Kept open indefinitely if unset.
+    pub idle_timeout_ms: Option<u64>,
+    /// Maximum number of prepared statements cached per connection.
+    /// `0` disables caching.
+    pub statement_cache_size: usize,
+    /// Reject queries that receive arguments but have no parameters,
+    /// instead of ignoring the arguments.
+    pub strict_args: bool,
+    /// Maximum number of distinct databases to keep connections open to.
+    /// The least recently used database is closed when the limit is exceeded.
+    pub max_databases: Option<usize>,
+    /// Serialize `daterange` values as
+    /// `{"from": "2024-01-01", "to": "2024-12-31", "inclusive_end": false}`.
+    pub date_range_bounds: bool,
+    /// Return `NaN` numeric values as `null` instead of the string `"NaN"`.
+    pub numeric_nan_as_null: bool,
+    /// Return finite numeric values as JSON numbers instead of strings.
+    /// Clients may lose precision when parsing them.
+    pub numeric_as_number: bool,
+    /// Unit of numeric arguments bound to `timestamptz`, `timestamp` and
+    /// `date` parameters.
+    pub epoch_args_unit: EpochUnit,
+    /// Accept any server certificate for `sslmode=prefer` and `require`,
+    /// including self-signed and expired ones.
+    /// Connections are then open to man-in-the-middle attacks.
+    pub allow_invalid_certs: bool,
+    /// Never connect without TLS, even with `sslmode=disable` or when a
+    /// TLS connection with `sslmode=prefer` fails, so credentials are never
+    /// sent in plaintext.
+    pub forbid_plaintext: bool,
+    /// Timeout for establishing connections, if the connection URI doesn't
+    /// set `connect_timeout`. Waits indefinitely if unset.
+    pub connect_timeout_ms: Option<u64>,
+    /// `application_name` of connections, shown in `pg_stat_activity`, if
+    /// the connection URI doesn't set it.
+    pub application_name: Option<String>,
+    /// Command-line `options` sent to the server, like
+    /// `-c search_path=app`, if the connection URI doesn't set them.
+    pub options: Option<String>,
+}
+
+impl Default for PostgresConfig {
+    fn default() -> Self {
+        Self {
+            untyped_args_as_text: false,
+            pool_size: 10,
+            pool_sizes: HashMap::new(),
+            acquire_timeout_ms: None,
+            min_idle: 0,
+            idle_timeout_ms: None,
+            statement_cache_size: 100,
+            strict_args: false,
+            max_databases: None,
+            date_range_bounds: false,
+            numeric_nan_as_null: false,
+            numeric_as_number: false,
+            epoch_args_unit: EpochUnit::Milliseconds,
+            allow_invalid_certs: false,
+            forbid_plaintext: false,
+            connect_timeout_ms: None,
+            application_name: Some("daprox".to_string()),
+            options: None,
+        }
+    }
+}
+
+/// A database connection along with its prepared statements.
+struct Connection {
+    client: Client,
+    statements: StatementCache,
+    notices: NoticeTarget,
+    canceller: Canceller,
+}
+
+/// Where the notices of a connection are sent.
+///
+/// Set to the notice sink of the query that currently uses the connection.
+type NoticeTarget = Arc<std::sync::Mutex<Option<NoticeSink>>>;
+
+/// Where the notifications of a connection are sent.
+///
+/// Only set for connections that listen on channels, notifications are
+/// dropped otherwise.
+type NotificationTarget = Arc<std::sync::Mutex<Option<NotificationSender>>>;
+
+/// Counter of connections that were closed because of an error.
+const LOST_CONNECTIONS_COUNTER: &str = "daprox_connections_lost_total";
+
+/// Drive a connection until it is closed.
+///
+/// Notices are forwarded to the [`NoticeTarget`] of the connection, and
+/// only logged if no query collects them.
+/// Notifications are forwarded to the [`NotificationTarget`].
+///
+/// Once this returns, the [`Client`] of the connection reports itself as
+/// closed, so the pool discards it instead of handing it out again, and
+/// queries still running on it fail with a "connection lost" error.
+async fn drive_connection<S, T>(
+    mut connection: tokio_postgres::Connection<S, T>,
+    notices: NoticeTarget,
+    notifications: NotificationTarget,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut messages = futures::stream::poll_fn(|cx| connection.poll_message(cx));
+    while let Some(message) = messages.next().await {
+        match message {
+            Ok(AsyncMessage::Notice(notice)) => match &*notices.lock().unwrap() {
+                Some(sink) => sink.push(Notice {
+                    severity: notice.severity().to_string(),
+                    code: notice.code().code().to_string(),
+                    message: notice.message().to_string(),
+                    detail: notice.detail().map(|s| s.to_string()),
+                    hint: notice.hint().map(|s| s.to_string()),
+                }),
+                None => tracing::debug!(
+                    severity = notice.severity(),
+                    "database notice: {}",
+                    notice.message()
+                ),
+            },
+            Ok(AsyncMessage::Notification(notification)) => {
+                let mut target = notifications.lock().unwrap();
+                if let Some(sender) = &*target {
+                    let sent = sender.send(Notification {
+                        channel: notification.channel().to_string(),
+                        payload: notification.payload().to_string(),
+                        process_id: notification.process_id(),
+                    });
+                    if !sent {
+                        *target = None;
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!("Database connection lost: {}", e);
+                metrics::increment_counter!(LOST_CONNECTIONS_COUNTER);
+                break;
+            }
+        }
+    }
+}
 
 /// A [`ServerCertVerifier`] that accepts any certificate.
 struct NoopCertVerifier;
@@ -32,84 +272,249 @@ impl ServerCertVerifier for NoopCertVerifier {
     }
 }
 
-#[cfg(feature = "rustls")]
-async fn start_connection_rustls(uri: &str) -> Result<Client, anyhow::Error> {
-    let mut config = rustls::ClientConfig::builder()
-        .with_safe_defaults()
-        .with_root_certificates(rustls::RootCertStore::empty())
-        .with_no_client_auth();
-
-    // FIXME: decide on verifier based on sslmode!
-    config
-        .dangerous()
-        .set_certificate_verifier(Arc::new(NoopCertVerifier));
-    let tls = tokio_postgres_rustls::MakeRustlsConnect::new(config);
-    let (client, connection) = tokio_postgres::connect(uri, tls).await?;
+/// Verifies the certificate chain, but not that the certificate is valid for
+/// the server hostname, as required by `sslmode=verify-ca`.
+struct CaCertVerifier(rustls::client::WebPkiVerifier);
 
-    // TODO: handle connection future better?
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            tracing::warn!("connection error: {}", e);
+impl ServerCertVerifier for CaCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        server_name: &rustls::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        match self.0.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        ) {
+            // rustls only reports the webpki error as text.
+            Err(rustls::Error::InvalidCertificateData(msg))
+                if msg.contains("CertNotValidForName") =>
+            {
+                Ok(rustls::client::ServerCertVerified::assertion())
+            }
+            other => other,
         }
-    });
-    Ok(client)
+    }
 }
 
-async fn start_connection_insecure(uri: &str) -> Result<Client, anyhow::Error> {
-    let (client, connection) = tokio_postgres::connect(&uri, tokio_postgres::NoTls).await?;
-    // TODO: handle connection future better?
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            tracing::warn!("connection error: {}", e);
+/// The `sslmode` connection parameter.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum SslMode {
+    Disable,
+    Prefer,
+    /// Use TLS and verify that the certificate is signed by a trusted CA.
+    /// Any certificate is accepted if `allow_invalid_certs` is set.
+    Require,
+    /// Verify that the certificate is signed by a trusted CA.
+    VerifyCa,
+    /// Verify the certificate chain and the server hostname.
+    VerifyFull,
+}
+
+/// Trusted root certificates for verifying the server certificate.
+///
+/// Loaded from the `sslrootcert` file if given, and from the platform trust
+/// store otherwise.
+#[cfg(feature = "rustls")]
+fn root_cert_store(root_cert: Option<&str>) -> Result<rustls::RootCertStore, anyhow::Error> {
+    let mut roots = rustls::RootCertStore::empty();
+    match root_cert {
+        Some(path) => {
+            let file = std::fs::File::open(path)
+                .with_context(|| format!("Could not open sslrootcert '{path}'"))?;
+            let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(file))
+                .with_context(|| format!("Could not read sslrootcert '{path}'"))?;
+            for cert in certs {
+                roots.add(&rustls::Certificate(cert))?;
+            }
         }
-    });
-    Ok(client)
+        None => {
+            let certs = rustls_native_certs::load_native_certs()
+                .context("Could not load platform root certificates")?;
+            for cert in certs {
+                if let Err(err) = roots.add(&rustls::Certificate(cert.0)) {
+                    tracing::debug!("Skipping invalid platform root certificate: {}", err);
+                }
+            }
+        }
+    }
+    Ok(roots)
 }
 
-async fn start_connection(uri: &str) -> Result<Client, anyhow::Error> {
+#[cfg(feature = "rustls")]
+async fn start_connection_rustls(
+    pg_config: &tokio_postgres::Config,
+    mode: SslMode,
+    root_cert: Option<&str>,
+    allow_invalid_certs: bool,
+) -> Result<(Client, NoticeTarget, NotificationTarget, Canceller), anyhow::Error> {
+    let builder = rustls::ClientConfig::builder().with_safe_defaults();
+    let config = match mode {
+        SslMode::VerifyFull => builder
+            .with_root_certificates(root_cert_store(root_cert)?)
+            .with_no_client_auth(),
+        SslMode::Disable | SslMode::Prefer | SslMode::Require if allow_invalid_certs => builder
+            .with_custom_certificate_verifier(Arc::new(NoopCertVerifier))
+            .with_no_client_auth(),
+        SslMode::Disable | SslMode::Prefer | SslMode::Require | SslMode::VerifyCa => {
+            let verifier = rustls::client::WebPkiVerifier::new(root_cert_store(root_cert)?, None);
+            builder
+                .with_custom_certificate_verifier(Arc::new(CaCertVerifier(verifier)))
+                .with_no_client_auth()
+        }
+    };
+
+    let tls = tokio_postgres_rustls::MakeRustlsConnect::new(config);
+    let mut pg_config = pg_config.clone();
+    pg_config.ssl_mode(tokio_postgres::config::SslMode::Require);
+    let (client, connection) = pg_config
+        .connect(tls.clone())
+        .await
+        .map_err(connect_error)?;
+
+    let notices = NoticeTarget::default();
+    let notifications = NotificationTarget::default();
+    tokio::spawn(drive_connection(
+        connection,
+        notices.clone(),
+        notifications.clone(),
+    ));
+    let canceller = Canceller::rustls(client.cancel_token(), tls);
+    Ok((client, notices, notifications, canceller))
+}
+
+async fn start_connection_insecure(
+    pg_config: &tokio_postgres::Config,
+) -> Result<(Client, NoticeTarget, NotificationTarget, Canceller), anyhow::Error> {
+    let mut pg_config = pg_config.clone();
+    pg_config.ssl_mode(tokio_postgres::config::SslMode::Disable);
+    let (client, connection) = pg_config
+        .connect(tokio_postgres::NoTls)
+        .await
+        .map_err(connect_error)?;
+    let notices = NoticeTarget::default();
+    let notifications = NotificationTarget::default();
+    tokio::spawn(drive_connection(
+        connection,
+        notices.clone(),
+        notifications.clone(),
+    ));
+    let canceller = Canceller::insecure(client.cancel_token());
+    Ok((client, notices, notifications, canceller))
+}
+
+/// Connection parameters that are handled by daprox instead of being passed
+/// on to tokio-postgres.
+const CONNECTION_PARAMS: &[&str] = &[
+    "sslmode",
+    "sslrootcert",
+    "connect_timeout",
+    "application_name",
+    "options",
+];
+
+/// Connect to the database at `uri`.
+///
+/// The `connect_timeout`, `application_name` and `options` parameters fall
+/// back to the defaults in `config` if the URI doesn't set them.
+///
+/// Unless `allow_invalid_certs` is set, server certificates are verified for
+/// all TLS connections, even with `sslmode=require`.
+/// With `forbid_plaintext`, failing to establish TLS is always an error.
+async fn start_connection(
+    uri: &str,
+    config: &PostgresConfig,
+) -> Result<(Client, NoticeTarget, NotificationTarget, Canceller), anyhow::Error> {
     let url: Url = uri.parse()?;
-    let ssl_mode = url
-        .query_pairs()
-        .find_map(
-            |(name, value)| {
-                if name == "sslmode" {
-                    Some(value)
-                } else {
-                    None
-                }
-            },
-        )
-        .filter(|x| !x.trim().is_empty());
-
-    let (try_ssl, needs_ssl) = match ssl_mode.as_deref() {
-        Some("disable") => (false, false),
-        Some("allow" | "prefer") => (true, false),
-        Some("require" | "verifiy-ca" | "verify-full") => (true, true),
-        Some("") | None => (true, false),
+    let param = |key: &str| {
+        url.query_pairs()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.into_owned())
+            .filter(|x| !x.trim().is_empty())
+    };
+    let ssl_mode = param("sslmode");
+    let root_cert = param("sslrootcert");
+
+    let mode = match ssl_mode.as_deref() {
+        Some("disable") => SslMode::Disable,
+        Some("allow" | "prefer") | None => SslMode::Prefer,
+        Some("require") => SslMode::Require,
+        Some("verify-ca") => SslMode::VerifyCa,
+        Some("verify-full") => SslMode::VerifyFull,
         Some(other) => {
             bail!("Unsupported sslmode {}", other);
         }
     };
+    let try_ssl = mode != SslMode::Disable;
+    let needs_ssl = matches!(
+        mode,
+        SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull
+    );
+
+    let connect_timeout = match param("connect_timeout") {
+        // Like libpq, the URI specifies the timeout in seconds.
+        Some(secs) => {
+            let invalid = || ArgumentError(format!("Invalid connect_timeout '{secs}'"));
+            let secs: u64 = secs.trim().parse().map_err(|_| invalid())?;
+            Some(secs.checked_mul(1000).ok_or_else(invalid)?)
+        }
+        None => config.connect_timeout_ms,
+    };
+    let application_name = param("application_name").or_else(|| config.application_name.clone());
+    let options = param("options").or_else(|| config.options.clone());
 
     tracing::trace!(%uri, %try_ssl, %needs_ssl, "connecting to postgres server");
 
+    // tokio-postgres only understands the sslmodes up to `require`, and
+    // rejects `sslrootcert`. Certificates are verified by the TLS config.
+    // The other parameters are set on the config explicitly.
+    let mut url = url.clone();
+    let pairs = url
+        .query_pairs()
+        .filter(|(name, _)| !CONNECTION_PARAMS.contains(&name.as_ref()))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    if pairs.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+
+    let mut pg_config: tokio_postgres::Config = url.as_str().parse().map_err(connect_error)?;
+    if let Some(ms) = connect_timeout.filter(|ms| *ms > 0) {
+        pg_config.connect_timeout(std::time::Duration::from_millis(ms));
+    }
+    if let Some(name) = &application_name {
+        pg_config.application_name(name);
+    }
+    if let Some(options) = &options {
+        pg_config.options(options);
+    }
+
     #[cfg(feature = "rustls")]
     {
         if try_ssl {
-            let uri = if ssl_mode.is_none() {
-                let mut url = url.clone();
-                url.query_pairs_mut().append_pair("sslmode", "require");
-                url.to_string()
-            } else {
-                uri.to_string()
-            };
-
-            match start_connection_rustls(&uri).await {
+            match start_connection_rustls(
+                &pg_config,
+                mode,
+                root_cert.as_deref(),
+                config.allow_invalid_certs,
+            )
+            .await
+            {
                 Ok(client) => return Ok(client),
                 Err(e) => {
                     tracing::warn!("Failed to connect with rustls: {}", e);
-                    if needs_ssl {
-                        bail!("Failed to connect to Postgres server '{uri}': {}", e);
+                    if needs_ssl || config.forbid_plaintext {
+                        bail!("Failed to connect to Postgres server with TLS: {:#}", e);
                     }
                 }
             }
@@ -119,53 +524,179 @@ async fn start_connection(uri: &str) -> Result<Client, anyhow::Error> {
     #[cfg(not(feature = "rustls"))]
     {
         if needs_ssl {
-            bail!("Failed to connect to Postgres server '{uri}': TLS required, but not supported in this daproxy instance");
+            bail!("Failed to connect to Postgres server: TLS required, but not supported in this daproxy instance");
         }
     }
 
-    start_connection_insecure(uri).await
+    if config.forbid_plaintext {
+        bail!("Refusing to connect to Postgres server without TLS, since forbid_plaintext is set");
+    }
+    start_connection_insecure(&pg_config).await
 }
 
 impl PostgresProx {
-    pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(State {})))
+    pub fn new(config: PostgresConfig) -> Self {
+        if config.allow_invalid_certs {
+            tracing::warn!(
+                "Accepting invalid TLS certificates of Postgres servers: \
+                 connections are vulnerable to man-in-the-middle attacks"
+            );
+        }
+        let pools = match config.max_databases.and_then(NonZeroUsize::new) {
+            Some(max) => LruCache::new(max),
+            None => LruCache::unbounded(),
+        };
+        Self {
+            config,
+            state: Arc::new(Mutex::new(State { pools })),
+        }
     }
 
+    /// Open a new connection that is not managed by the pool.
     pub async fn connect(&self, uri: &str) -> Result<Client, anyhow::Error> {
-        start_connection(uri).await
+        let (client, _notices, _notifications, _canceller) =
+            start_connection(uri, &self.config).await?;
+        Ok(client)
     }
 
-    async fn query(&self, query: &SqlQuery) -> Result<Vec<Row>, anyhow::Error> {
-        let client = self.connect(&query.db).await?;
-        let rows = client.query(&query.query, &[]).await?;
-        Ok(rows)
+    /// Close all connection pools.
+    ///
+    /// Idle connections are closed immediately, connections that are in use
+    /// once they are returned.
+    pub async fn close(&self) {
+        let mut state = self.state.lock().await;
+        for (_uri, pool) in state.pools.iter() {
+            pool.close();
+        }
+        state.pools.clear();
     }
 
-    async fn query_stream(&self, query: &SqlQuery) -> Result<RowStream, anyhow::Error> {
-        let client = self.connect(&query.db).await?;
-        let params: Vec<&dyn ToSql> = vec![];
-        let rows = client.query_raw(&query.query, params).await?;
-        Ok(rows)
+    /// Close the connection pool of a database, like [`Self::close`].
+    ///
+    /// Requests waiting for a connection of the pool fail. A new pool is
+    /// opened if the database is queried again.
+    pub async fn close_pool(&self, uri: &str) {
+        if let Some(pool) = self.state.lock().await.pools.pop(uri) {
+            pool.close();
+        }
+    }
+
+    /// Get a pooled connection to the given database.
+    async fn connection(&self, uri: &str) -> Result<PooledConnection, anyhow::Error> {
+        let pool = {
+            let mut state = self.state.lock().await;
+            match state.pools.get(uri) {
+                Some(pool) => pool.clone(),
+                None => {
+                    let pool = Pool::new(uri, &self.config);
+                    if let Some((_uri, evicted)) = state.pools.push(uri.to_string(), pool.clone()) {
+                        tracing::debug!("Closing pool of least recently used database");
+                        evicted.close();
+                    }
+                    pool
+                }
+            }
+        };
+        pool.get().await
+    }
+
+    /// Get a pooled connection to the database of a query.
+    ///
+    /// Notices are sent to the notice sink of the query while the connection
+    /// is checked out.
+    async fn query_connection(&self, query: &SqlQuery) -> Result<PooledConnection, anyhow::Error> {
+        let conn = self.connection(&query.db).await?;
+        *conn.notices.lock().unwrap() = query.notices.clone();
+        Ok(conn)
+    }
+
+    /// Run the query and return a stream of the resulting rows.
+    ///
+    /// Rows are only read from the server as the stream is polled.
+    /// The connection is returned to the pool once it is dropped, so it must
+    /// be kept alive along with the stream, and the guard must be disarmed
+    /// once all rows were read.
+    async fn query_stream(
+        &self,
+        query: &SqlQuery,
+    ) -> Result<(PooledConnection, CancelGuard, Statement, RowStream), anyhow::Error> {
+        query.check_statement_count()?;
+        let mut pooled = self.query_connection(query).await?;
+        let guard = pooled.cancel_guard();
+        let conn = &mut *pooled;
+        let query_args = QueryArgs::new(query, self.config.epoch_args_unit)?;
+        let types = query_args.param_types();
+        let QueryArgs { sql, args } = query_args;
+
+        let (statement, rows) = cancel_on_drop(guard, async {
+            let statement = conn
+                .statements
+                .prepare(&conn.client, &sql, &types, self.config.untyped_args_as_text)
+                .await
+                .map_err(database_error)?;
+            let params = statement_params(&statement, &args, self.config.strict_args)?;
+            let rows = conn
+                .client
+                .query_raw(&statement, params)
+                .await
+                .map_err(database_error)?;
+            Ok::<_, anyhow::Error>((statement, rows))
+        })
+        .await?;
+        // Cancels the query if the rows are not read to the end.
+        let guard = pooled.cancel_guard();
+        Ok((pooled, guard, statement, rows))
+    }
+
+    /// Whether the query can be streamed with [`Self::query_stream`].
+    ///
+    /// Cursors and read-only transactions need a transaction that spans the
+    /// whole query, so those are always buffered.
+    fn can_stream(query: &SqlQuery) -> bool {
+        // Invalid queries are rejected when they are run unstreamed.
+        matches!(use_simple_protocol(query), Ok(false))
+            && !query.fetch_cursors
+            && !query.read_only_tx
     }
 }
 
+#[async_trait]
 impl SqlBackend for PostgresProx {
     async fn query_json_maps(
         &self,
         query: daprox_core::SqlQuery,
     ) -> Result<Vec<serde_json::Value>, anyhow::Error> {
-        let client = self.connect(&query.db).await?;
-        let rows = client.query(&query.query, &[]).await?;
-        rows.into_iter().map(|r| row_to_json_map(&r)).collect()
+        if use_simple_protocol(&query)? {
+            let rows = self.simple_query_rows(&query).await?;
+            return Ok(rows
+                .iter()
+                .map(|row| simple_row_to_json_map(row, query.omit_nulls))
+                .collect());
+        }
+
+        let opts = JsonOptions::new(&self.config, &query)?;
+        let (_statement, rows, cursors) = self.query_rows(&query, &opts).await?;
+        rows.iter()
+            .map(|r| row_to_json_map_with_cursors(r, &cursors, &opts))
+            .collect()
     }
 
     async fn query_column_arrays(
         &self,
         query: daprox_core::SqlQuery,
     ) -> Result<(ColumnNames, Vec<Vec<JsonValue>>), anyhow::Error> {
-        let client = self.connect(&query.db).await?;
+        if use_simple_protocol(&query)? {
+            let rows = self.simple_query_rows(&query).await?;
+            let names = rows
+                .first()
+                .map(|r| r.columns().iter().map(|c| c.name().to_string()).collect())
+                .unwrap_or_default();
+            let arrays = rows.iter().map(simple_row_to_json_columns).collect();
+            return Ok((names, arrays));
+        }
 
-        let rows = client.query(&query.query, &[]).await?;
+        let opts = JsonOptions::new(&self.config, &query)?;
+        let (_statement, rows, cursors) = self.query_rows(&query, &opts).await?;
 
         let names = if let Some(first) = rows.first() {
             first
@@ -178,19 +709,681 @@ impl SqlBackend for PostgresProx {
         };
 
         let arrays = rows
-            .into_iter()
-            .map(|r| row_to_json_columns(&r))
+            .iter()
+            .map(|r| row_to_json_columns_with_cursors(r, &cursors, &opts))
             .collect::<Result<_, _>>()?;
 
         Ok((names, arrays))
     }
+
+    async fn query_typed_columns(
+        &self,
+        query: daprox_core::SqlQuery,
+    ) -> Result<(Vec<ColumnInfo>, Vec<Vec<JsonValue>>), anyhow::Error> {
+        let opts = JsonOptions::new(&self.config, &query)?;
+        let (statement, rows, cursors) = self.query_rows(&query, &opts).await?;
+
+        let columns = statement
+            .columns()
+            .iter()
+            .map(|c| ColumnInfo {
+                name: c.name().to_string(),
+                type_: opts.column_type(c.type_()),
+            })
+            .collect();
+
+        let arrays = rows
+            .iter()
+            .map(|r| row_to_json_columns_with_cursors(r, &cursors, &opts))
+            .collect::<Result<_, _>>()?;
+
+        Ok((columns, arrays))
+    }
+
+    async fn query_json_map_stream(&self, query: SqlQuery) -> Result<JsonRowStream, anyhow::Error> {
+        if !Self::can_stream(&query) {
+            let rows = self.query_json_maps(query).await?;
+            return Ok(futures::stream::iter(rows.into_iter().map(Ok)).boxed());
+        }
+
+        let opts = JsonOptions::new(&self.config, &query)?;
+        let (conn, guard, _statement, rows) = self.query_stream(&query).await?;
+        let stream =
+            ConnectionRows::new(conn, guard, rows, move |row| row_to_json_map(&row, &opts));
+        Ok(stream.boxed())
+    }
+
+    /// Unlike [`Self::query_column_arrays`], the column names are also
+    /// returned if the query has no rows.
+    async fn query_column_array_stream(
+        &self,
+        query: SqlQuery,
+    ) -> Result<(ColumnNames, JsonRowStream), anyhow::Error> {
+        if !Self::can_stream(&query) {
+            let (names, rows) = self.query_column_arrays(query).await?;
+            let rows = rows.into_iter().map(|row| Ok(JsonValue::Array(row)));
+            return Ok((names, futures::stream::iter(rows).boxed()));
+        }
+
+        let opts = JsonOptions::new(&self.config, &query)?;
+        let (conn, guard, statement, rows) = self.query_stream(&query).await?;
+        let names = statement
+            .columns()
+            .iter()
+            .map(|c| c.name().to_string())
+            .collect();
+        let stream = ConnectionRows::new(conn, guard, rows, move |row| {
+            Ok(JsonValue::Array(row_to_json_columns(&row, &opts)?))
+        });
+        Ok((names, stream.boxed()))
+    }
+}
+
+/// Converted rows of a streamed query, which keep the connection checked out
+/// until the stream is dropped.
+///
+/// If the stream is dropped before all rows were read, like when the client
+/// disconnects, the query is cancelled.
+struct ConnectionRows<F> {
+    conn: Option<PooledConnection>,
+    guard: Option<CancelGuard>,
+    rows: Pin<Box<RowStream>>,
+    convert: F,
+}
+
+impl<F> ConnectionRows<F>
+where
+    F: FnMut(Row) -> Result<JsonValue, anyhow::Error>,
+{
+    fn new(conn: PooledConnection, guard: CancelGuard, rows: RowStream, convert: F) -> Self {
+        Self {
+            conn: Some(conn),
+            guard: Some(guard),
+            rows: Box::pin(rows),
+            convert,
+        }
+    }
+
+    /// The query ended, so there is nothing to cancel.
+    fn finish(&mut self) {
+        if let Some(guard) = self.guard.take() {
+            guard.disarm();
+        }
+    }
+}
+
+impl<F> futures::Stream for ConnectionRows<F>
+where
+    F: FnMut(Row) -> Result<JsonValue, anyhow::Error> + Unpin,
+{
+    type Item = Result<JsonValue, anyhow::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match futures::ready!(this.rows.poll_next_unpin(cx)) {
+            Some(Ok(row)) => Poll::Ready(Some((this.convert)(row))),
+            Some(Err(err)) => {
+                // The server ends the query after an error.
+                this.finish();
+                Poll::Ready(Some(Err(database_error(err))))
+            }
+            None => {
+                this.finish();
+                Poll::Ready(None)
+            }
+        }
+    }
+}
+
+impl<F> Drop for ConnectionRows<F> {
+    fn drop(&mut self) {
+        // The guard marks the connection for closing, so it must be dropped
+        // before the connection is returned to the pool.
+        drop(self.guard.take());
+        drop(self.conn.take());
+    }
+}
+
+/// Rows fetched from refcursors, keyed by cursor name.
+type CursorRows = HashMap<String, JsonValue>;
+
+impl PostgresProx {
+    /// Run the query with the simple query protocol.
+    async fn simple_query_rows(
+        &self,
+        query: &SqlQuery,
+    ) -> Result<Vec<SimpleQueryRow>, anyhow::Error> {
+        query.check_statement_count()?;
+        // Without parameters, the limit and offset are sent as literals.
+        let sql = if query.limit.is_some() || query.offset.is_some() {
+            Cow::Owned(append_limit(
+                &query.query,
+                query.limit.map(|limit| limit.to_string()),
+                query.offset.map(|offset| offset.to_string()),
+            )?)
+        } else {
+            Cow::Borrowed(query.query.as_str())
+        };
+
+        let conn = self.query_connection(query).await?;
+        let rows = cancel_on_drop(conn.cancel_guard(), conn.client.simple_query(&sql))
+            .await
+            .map_err(database_error)?
+            .into_iter()
+            .filter_map(|msg| match msg {
+                SimpleQueryMessage::Row(row) => Some(row),
+                _ => None,
+            })
+            .take(query.collect_limit())
+            .collect();
+        Ok(rows)
+    }
+
+    /// Run the query, fetching all referenced cursors if requested.
+    async fn query_rows(
+        &self,
+        query: &SqlQuery,
+        opts: &JsonOptions,
+    ) -> Result<(Statement, Vec<Row>, CursorRows), anyhow::Error> {
+        query.check_statement_count()?;
+        let mut pooled = self.query_connection(query).await?;
+        let guard = pooled.cancel_guard();
+        let conn = &mut *pooled;
+        let query_args = QueryArgs::new(query, self.config.epoch_args_unit)?;
+        let types = query_args.param_types();
+        let QueryArgs { sql, args } = query_args;
+        let untyped_as_text = self.config.untyped_args_as_text;
+        let strict = self.config.strict_args;
+
+        cancel_on_drop(guard, async {
+            if !query.fetch_cursors && !query.read_only_tx {
+                let statement = conn
+                    .statements
+                    .prepare(&conn.client, &sql, &types, untyped_as_text)
+                    .await
+                    .map_err(database_error)?;
+                let params = statement_params(&statement, &args, strict)?;
+                let rows = conn
+                    .client
+                    .query_raw(&statement, params)
+                    .await
+                    .map_err(database_error)?;
+                let rows = collect_rows(rows, query.collect_limit()).await?;
+                return Ok((statement, rows, CursorRows::new()));
+            }
+
+            // Cursors are only valid until the end of the transaction that
+            // created them, so everything must run in the same transaction.
+            let tx = conn
+                .client
+                .build_transaction()
+                .read_only(query.read_only_tx)
+                .start()
+                .await
+                .map_err(database_error)?;
+            let statement = conn
+                .statements
+                .prepare(&tx, &sql, &types, untyped_as_text)
+                .await
+                .map_err(database_error)?;
+            let params = statement_params(&statement, &args, strict)?;
+            let rows = tx
+                .query_raw(&statement, params)
+                .await
+                .map_err(database_error)?;
+            let rows = collect_rows(rows, query.collect_limit()).await?;
+
+            let cursors = if query.fetch_cursors {
+                fetch_cursors(&tx, &rows, opts).await?
+            } else {
+                CursorRows::new()
+            };
+
+            tx.commit().await.map_err(database_error)?;
+            Ok::<_, anyhow::Error>((statement, rows, cursors))
+        })
+        .await
+    }
+}
+
+/// Run a query on a connection, and cancel it if the future is dropped
+/// before the query finished.
+async fn cancel_on_drop<T>(guard: CancelGuard, query: impl std::future::Future<Output = T>) -> T {
+    let output = query.await;
+    guard.disarm();
+    output
+}
+
+/// Collect at most `limit` rows of a row stream.
+///
+/// Remaining rows are discarded by the connection.
+async fn collect_rows(rows: RowStream, limit: usize) -> Result<Vec<Row>, anyhow::Error> {
+    rows.take(limit)
+        .map(|row| row.map_err(database_error))
+        .try_collect()
+        .await
+}
+
+/// Convert errors reported by the server into a [`DatabaseError`].
+///
+/// Errors of connections that were lost are reported as unavailable.
+fn database_error(err: tokio_postgres::Error) -> anyhow::Error {
+    if err.is_closed() {
+        // The cause is logged by the task driving the connection.
+        return DatabaseError {
+            message: "Database connection lost".to_string(),
+            kind: DatabaseErrorKind::Unavailable,
+            ..Default::default()
+        }
+        .into();
+    }
+    let Some(db) = err.as_db_error() else {
+        return err.into();
+    };
+
+    let position = match db.position() {
+        Some(tokio_postgres::error::ErrorPosition::Original(pos)) => Some(*pos),
+        _ => None,
+    };
+    DatabaseError {
+        code: Some(db.code().code().to_string()),
+        message: db.message().to_string(),
+        detail: db.detail().map(|s| s.to_string()),
+        hint: db.hint().map(|s| s.to_string()),
+        position,
+        kind: error_kind(db.code()),
+    }
+    .into()
+}
+
+/// Convert errors from establishing a connection.
+///
+/// Errors not reported by the server mean that it could not be reached.
+fn connect_error(err: tokio_postgres::Error) -> anyhow::Error {
+    if err.as_db_error().is_some() {
+        return database_error(err);
+    }
+    DatabaseError {
+        message: err.to_string(),
+        kind: DatabaseErrorKind::Unavailable,
+        ..Default::default()
+    }
+    .into()
+}
+
+/// Categorize an error by its SQLSTATE code.
+fn error_kind(code: &SqlState) -> DatabaseErrorKind {
+    let undefined = [
+        SqlState::UNDEFINED_TABLE,
+        SqlState::UNDEFINED_COLUMN,
+        SqlState::UNDEFINED_FUNCTION,
+        SqlState::UNDEFINED_OBJECT,
+        SqlState::INVALID_SCHEMA_NAME,
+    ];
+    if undefined.contains(code) {
+        return DatabaseErrorKind::UndefinedObject;
+    }
+    if code == &SqlState::INSUFFICIENT_PRIVILEGE {
+        return DatabaseErrorKind::PermissionDenied;
+    }
+    if code == &SqlState::CANNOT_CONNECT_NOW || code == &SqlState::TOO_MANY_CONNECTIONS {
+        return DatabaseErrorKind::Unavailable;
+    }
+
+    match &code.code()[..2] {
+        // Data exception, syntax error or access rule violation.
+        "22" | "42" => DatabaseErrorKind::InvalidQuery,
+        "23" => DatabaseErrorKind::ConstraintViolation,
+        "28" => DatabaseErrorKind::AuthenticationFailed,
+        // Connection exception.
+        "08" => DatabaseErrorKind::Unavailable,
+        _ => DatabaseErrorKind::Other,
+    }
+}
+
+/// Whether the query should be sent with the simple query protocol.
+///
+/// Typed output and transactional options always use the extended protocol.
+/// The simple protocol can not bind arguments, so requesting it for a query
+/// with arguments is an error.
+fn use_simple_protocol(query: &SqlQuery) -> Result<bool, ArgumentError> {
+    if query.fetch_cursors || query.read_only_tx {
+        return Ok(false);
+    }
+    let has_args = query.args.is_some() || query.kw_args.is_some();
+    match query.protocol {
+        QueryProtocol::Extended => Ok(false),
+        QueryProtocol::Simple if has_args => Err(ArgumentError(
+            "args and kw_args are not supported with the simple protocol".to_string(),
+        )),
+        QueryProtocol::Simple => Ok(true),
+        QueryProtocol::Auto => Ok(!has_args),
+    }
+}
+
+fn simple_row_value(row: &SimpleQueryRow, index: usize) -> JsonValue {
+    row.get(index)
+        .map(|v| JsonValue::String(v.to_string()))
+        .unwrap_or(JsonValue::Null)
+}
+
+fn simple_row_to_json_map(row: &SimpleQueryRow, omit_nulls: bool) -> JsonValue {
+    let map = row
+        .columns()
+        .iter()
+        .enumerate()
+        .map(|(index, col)| (col.name().to_string(), simple_row_value(row, index)))
+        .filter(|(_, value)| !(omit_nulls && value.is_null()))
+        .collect();
+    JsonValue::Object(map)
+}
+
+fn simple_row_to_json_columns(row: &SimpleQueryRow) -> Vec<JsonValue> {
+    (0..row.len())
+        .map(|index| simple_row_value(row, index))
+        .collect()
+}
+
+/// Fetch all rows from the cursors referenced by `refcursor` columns.
+async fn fetch_cursors(
+    tx: &Transaction<'_>,
+    rows: &[Row],
+    opts: &JsonOptions,
+) -> Result<CursorRows, anyhow::Error> {
+    let mut cursors = CursorRows::new();
+    for row in rows {
+        for (index, col) in row.columns().iter().enumerate() {
+            if col.type_() != &Type::REFCURSOR {
+                continue;
+            }
+            let Some(CursorName(name)) = row.try_get::<_, Option<CursorName>>(index)? else {
+                continue;
+            };
+            if cursors.contains_key(&name) {
+                continue;
+            }
+
+            let sql = format!("FETCH ALL FROM {}", quote_ident(&name));
+            let fetched = tx
+                .query(&sql, &[])
+                .await
+                .map_err(database_error)?
+                .iter()
+                .map(|r| row_to_json_map(r, opts))
+                .collect::<Result<Vec<_>, _>>()?;
+            cursors.insert(name, JsonValue::Array(fetched));
+        }
+    }
+    Ok(cursors)
+}
+
+/// Replace a cursor name with the rows fetched from that cursor.
+fn inline_cursor(value: &mut JsonValue, cursors: &CursorRows) {
+    if let JsonValue::String(name) = value {
+        if let Some(rows) = cursors.get(name.as_str()) {
+            *value = rows.clone();
+        }
+    }
+}
+
+fn row_to_json_map_with_cursors(
+    row: &Row,
+    cursors: &CursorRows,
+    opts: &JsonOptions,
+) -> Result<JsonValue, anyhow::Error> {
+    let mut value = row_to_json_map(row, opts)?;
+    if let JsonValue::Object(map) = &mut value {
+        for col in row.columns() {
+            if col.type_() == &Type::REFCURSOR {
+                if let Some(v) = map.get_mut(col.name()) {
+                    inline_cursor(v, cursors);
+                }
+            }
+        }
+    }
+    Ok(value)
+}
+
+fn row_to_json_columns_with_cursors(
+    row: &Row,
+    cursors: &CursorRows,
+    opts: &JsonOptions,
+) -> Result<Vec<JsonValue>, anyhow::Error> {
+    let mut values = row_to_json_columns(row, opts)?;
+    for (index, col) in row.columns().iter().enumerate() {
+        if col.type_() == &Type::REFCURSOR {
+            inline_cursor(&mut values[index], cursors);
+        }
+    }
+    Ok(values)
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// The name of a portal, as returned in `refcursor` columns.
+struct CursorName(String);
+
+impl<'a> FromSql<'a> for CursorName {
+    fn from_sql(
+        _ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(Self(std::str::from_utf8(raw)?.to_string()))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty == &Type::REFCURSOR
+    }
+}
+
+/// The label of a value of a user-defined enum type.
+struct EnumLabel(String);
+
+impl<'a> FromSql<'a> for EnumLabel {
+    fn from_sql(
+        _ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(Self(std::str::from_utf8(raw)?.to_string()))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(ty.kind(), Kind::Enum(_))
+    }
+}
+
+/// The text of a `json` or `jsonb` value.
+struct JsonText(String);
+
+impl<'a> FromSql<'a> for JsonText {
+    fn from_sql(
+        ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        // Binary jsonb values start with a format version.
+        let raw = match (ty, raw.split_first()) {
+            (&Type::JSONB, Some((1, text))) => text,
+            (&Type::JSONB, _) => return Err("unsupported jsonb version".into()),
+            _ => raw,
+        };
+        Ok(Self(std::str::from_utf8(raw)?.to_string()))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty == &Type::JSON || ty == &Type::JSONB
+    }
+}
+
+/// A value of the `unknown` pseudo type, like an untyped string literal.
+///
+/// Sent in the binary format as its text.
+struct UnknownText(String);
+
+impl<'a> FromSql<'a> for UnknownText {
+    fn from_sql(
+        _ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(Self(std::str::from_utf8(raw)?.to_string()))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty == &Type::UNKNOWN
+    }
+}
+
+/// A value of a type without a JSON conversion, read as text.
+///
+/// Values are sent in the binary format, so only types whose binary format
+/// is their text are accepted, like `name`, `xml`, `citext` or `unknown`,
+/// and domains over them. Other types must be cast to `text` in the query.
+struct FallbackText(String);
+
+impl FallbackText {
+    /// Whether values of the type, or the elements of an array type, can be
+    /// read as text.
+    fn reads(ty: &Type) -> bool {
+        match ty.kind() {
+            Kind::Array(inner) => Self::accepts(inner),
+            _ => Self::accepts(ty),
+        }
+    }
+}
+
+impl<'a> FromSql<'a> for FallbackText {
+    fn from_sql(
+        ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        match std::str::from_utf8(raw) {
+            Ok(text) if !text.contains('\0') => Ok(Self(text.to_string())),
+            _ => Err(format!("values of type '{ty}' can not be read as text").into()),
+        }
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        match ty.kind() {
+            Kind::Domain(inner) => Self::accepts(inner),
+            _ => {
+                matches!(*ty, Type::NAME | Type::XML | Type::UNKNOWN | Type::TEXT)
+                    || ty.name() == "citext"
+            }
+        }
+    }
+}
+
+/// Options that control how column values are converted to JSON.
+#[derive(Clone, Debug)]
+struct JsonOptions {
+    /// Serialize `daterange` values as `from`/`to` date strings.
+    date_range_bounds: bool,
+    /// Convert `NaN` numerics to null.
+    numeric_nan_as_null: bool,
+    /// Convert finite numerics to JSON numbers.
+    numeric_as_number: bool,
+    /// Format timestamps as UTC with a `Z` suffix.
+    normalize_timestamps_utc: bool,
+    /// Timezone of naive timestamps when normalizing to UTC.
+    assume_timezone: FixedOffset,
+    bytea_encoding: ByteaEncoding,
+    json_encoding: JsonEncoding,
+    /// Leave out null values in rows converted to maps.
+    omit_nulls: bool,
+    /// Read values of unsupported types as text.
+    unsupported_types_as_text: bool,
+}
+
+impl JsonOptions {
+    fn new(config: &PostgresConfig, query: &SqlQuery) -> Result<Self, ArgumentError> {
+        let assume_timezone = match &query.assume_timezone {
+            Some(tz) => datetime::parse_offset(tz)?,
+            None => FixedOffset::east_opt(0).unwrap(),
+        };
+        Ok(Self {
+            date_range_bounds: config.date_range_bounds,
+            numeric_nan_as_null: config.numeric_nan_as_null,
+            numeric_as_number: config.numeric_as_number,
+            normalize_timestamps_utc: query.normalize_timestamps_utc,
+            assume_timezone,
+            bytea_encoding: query.bytea_encoding,
+            json_encoding: query.json_encoding,
+            omit_nulls: query.omit_nulls,
+            unsupported_types_as_text: query.unsupported_types_as_text,
+        })
+    }
+
+    /// Map a Postgres type to the type of the values converted with these
+    /// options.
+    fn column_type(&self, ty: &Type) -> ColumnType {
+        match ty {
+            &Type::NUMERIC if self.numeric_as_number => ColumnType::Float64,
+            &Type::TIMESTAMP if self.normalize_timestamps_utc => ColumnType::TimestampTz,
+            &Type::JSON | &Type::JSONB if self.json_encoding == JsonEncoding::Text => {
+                ColumnType::Text
+            }
+            other => column_type(other),
+        }
+    }
+
+    fn numeric_json(&self, value: Numeric) -> JsonValue {
+        match value {
+            Numeric::NaN if self.numeric_nan_as_null => JsonValue::Null,
+            Numeric::Value(v) if self.numeric_as_number => v
+                .parse::<serde_json::Number>()
+                .map(JsonValue::Number)
+                .unwrap_or(JsonValue::String(v)),
+            n => JsonValue::String(n.to_text()),
+        }
+    }
+
+    fn bytea_json(&self, value: &[u8]) -> JsonValue {
+        let encoded = match self.bytea_encoding {
+            ByteaEncoding::Base64 => base64::engine::general_purpose::STANDARD.encode(value),
+            ByteaEncoding::Hex => {
+                let mut s = String::with_capacity(2 + value.len() * 2);
+                s.push_str("\\x");
+                for byte in value {
+                    s.push_str(&format!("{byte:02x}"));
+                }
+                s
+            }
+        };
+        JsonValue::String(encoded)
+    }
+
+    fn timestamptz_json(&self, value: DateTime<Utc>) -> JsonValue {
+        JsonValue::String(datetime::format_timestamptz(
+            value,
+            self.normalize_timestamps_utc,
+        ))
+    }
+
+    fn timestamp_json(&self, value: NaiveDateTime) -> JsonValue {
+        let assume_timezone = self
+            .normalize_timestamps_utc
+            .then_some(self.assume_timezone);
+        JsonValue::String(datetime::format_timestamp(value, assume_timezone))
+    }
 }
 
 fn row_column_to_json(
     row: &Row,
     column: &Column,
     index: usize,
+    opts: &JsonOptions,
 ) -> Result<JsonValue, anyhow::Error> {
+    // Enums are user-defined types without a fixed OID.
+    match column.type_().kind() {
+        Kind::Enum(_) => return Ok(get_column_json_value_with(row, index, |l: EnumLabel| l.0)?),
+        Kind::Array(inner) if matches!(inner.kind(), Kind::Enum(_)) => {
+            return Ok(get_column_json_array_with(row, index, |l: EnumLabel| l.0)?);
+        }
+        Kind::Composite(_) => return composite_column_json(row, index, opts),
+        _ => {}
+    }
+
     let value: JsonValue = match column.type_() {
         &Type::BOOL => get_column_json_value::<bool>(row, index)?,
         &Type::INT2 => get_column_json_value::<i16>(row, index)?,
@@ -201,8 +1394,57 @@ fn row_column_to_json(
         &Type::CHAR => get_column_json_value::<String>(row, index)?,
         &Type::VARCHAR => get_column_json_value::<String>(row, index)?,
         &Type::TEXT => get_column_json_value::<String>(row, index)?,
+        &Type::JSON | &Type::JSONB if opts.json_encoding == JsonEncoding::Text => {
+            get_column_json_value_with(row, index, |t: JsonText| t.0)?
+        }
         &Type::JSON => get_column_json_value::<JsonValue>(row, index)?,
         &Type::JSONB => get_column_json_value::<JsonValue>(row, index)?,
+        // Functions like `pg_sleep` return `void`, which has no value.
+        &Type::VOID => JsonValue::Null,
+        &Type::UNKNOWN => get_column_json_value_with(row, index, |t: UnknownText| t.0)?,
+        &Type::RECORD => composite_column_json(row, index, opts)?,
+        // hstore is defined by an extension, so it has no fixed OID.
+        ty if ty.name() == "hstore" => get_column_json_value_with(row, index, hstore_json)?,
+        &Type::REFCURSOR => row
+            .try_get::<_, Option<CursorName>>(index)?
+            .map(|c| JsonValue::String(c.0))
+            .unwrap_or(JsonValue::Null),
+        &Type::DATE_RANGE if opts.date_range_bounds => date_range_bounds_json(row, index)?,
+        &Type::INT4_RANGE => {
+            get_column_json_value_with(row, index, |r: Range<i32>| r.to_json(JsonValue::from))?
+        }
+        &Type::INT8_RANGE => {
+            get_column_json_value_with(row, index, |r: Range<i64>| r.to_json(JsonValue::from))?
+        }
+        &Type::NUM_RANGE => get_column_json_value_with(row, index, |r: Range<Numeric>| {
+            r.to_json(|n| opts.numeric_json(n))
+        })?,
+        &Type::TS_RANGE => get_column_json_value_with(row, index, |r: Range<NaiveDateTime>| {
+            r.to_json(|ts| opts.timestamp_json(ts))
+        })?,
+        &Type::TSTZ_RANGE => get_column_json_value_with(row, index, |r: Range<DateTime<Utc>>| {
+            r.to_json(|ts| opts.timestamptz_json(ts))
+        })?,
+        &Type::DATE_RANGE => get_column_json_value_with(row, index, date_range_json)?,
+        // Intervals are formatted as ISO 8601 durations.
+        &Type::INTERVAL => get_column_json_value_with(row, index, |i: Interval| i.to_iso8601())?,
+        // Numerics are returned as strings to retain their precision, unless
+        // `numeric_as_number` is set.
+        &Type::NUMERIC => get_column_json_value_with(row, index, |n| opts.numeric_json(n))?,
+        &Type::UUID => get_column_json_value_with(row, index, |u: uuid::Uuid| u.to_string())?,
+        &Type::TIMESTAMPTZ => {
+            get_column_json_value_with(row, index, |ts| opts.timestamptz_json(ts))?
+        }
+        &Type::TIMESTAMP => get_column_json_value_with(row, index, |ts| opts.timestamp_json(ts))?,
+        &Type::DATE => get_column_json_value_with(row, index, datetime::format_date)?,
+        &Type::BYTEA => get_column_json_value_with(row, index, |b: &[u8]| opts.bytea_json(b))?,
+        &Type::TIME => get_column_json_value_with(row, index, datetime::format_time)?,
+        &Type::INET | &Type::CIDR => {
+            get_column_json_value_with(row, index, |i: network::Inet| i.to_text())?
+        }
+        &Type::MACADDR => {
+            get_column_json_value_with(row, index, |m: network::MacAddr| m.to_text())?
+        }
         // Arrays.
         &Type::BOOL_ARRAY => get_column_json_array_as_value::<bool>(row, index)?,
         &Type::INT2_ARRAY => get_column_json_array_as_value::<i16>(row, index)?,
@@ -213,37 +1455,240 @@ fn row_column_to_json(
         &Type::CHAR_ARRAY => get_column_json_array_as_value::<String>(row, index)?,
         &Type::VARCHAR_ARRAY => get_column_json_array_as_value::<String>(row, index)?,
         &Type::TEXT_ARRAY => get_column_json_array_as_value::<String>(row, index)?,
+        &Type::JSON_ARRAY | &Type::JSONB_ARRAY if opts.json_encoding == JsonEncoding::Text => {
+            get_column_json_array_with(row, index, |t: JsonText| t.0)?
+        }
         &Type::JSON_ARRAY => get_column_json_array_as_value::<JsonValue>(row, index)?,
         &Type::JSONB_ARRAY => get_column_json_array_as_value::<JsonValue>(row, index)?,
+        &Type::NUMERIC_ARRAY => get_column_json_array_with(row, index, |n| opts.numeric_json(n))?,
+        &Type::UUID_ARRAY => get_column_json_array_with(row, index, |u: uuid::Uuid| u.to_string())?,
+        &Type::TIMESTAMPTZ_ARRAY => {
+            get_column_json_array_with(row, index, |ts| opts.timestamptz_json(ts))?
+        }
+        &Type::TIMESTAMP_ARRAY => {
+            get_column_json_array_with(row, index, |ts| opts.timestamp_json(ts))?
+        }
+        &Type::DATE_ARRAY => get_column_json_array_with(row, index, datetime::format_date)?,
+        &Type::BYTEA_ARRAY => {
+            get_column_json_array_with(row, index, |b: &[u8]| opts.bytea_json(b))?
+        }
+        &Type::TIME_ARRAY => get_column_json_array_with(row, index, datetime::format_time)?,
+        &Type::INET_ARRAY | &Type::CIDR_ARRAY => {
+            get_column_json_array_with(row, index, |i: network::Inet| i.to_text())?
+        }
+        &Type::MACADDR_ARRAY => {
+            get_column_json_array_with(row, index, |m: network::MacAddr| m.to_text())?
+        }
+        &Type::INT4_RANGE_ARRAY => {
+            get_column_json_array_with(row, index, |r: Range<i32>| r.to_json(JsonValue::from))?
+        }
+        &Type::INT8_RANGE_ARRAY => {
+            get_column_json_array_with(row, index, |r: Range<i64>| r.to_json(JsonValue::from))?
+        }
+        &Type::NUM_RANGE_ARRAY => get_column_json_array_with(row, index, |r: Range<Numeric>| {
+            r.to_json(|n| opts.numeric_json(n))
+        })?,
+        &Type::TS_RANGE_ARRAY => {
+            get_column_json_array_with(row, index, |r: Range<NaiveDateTime>| {
+                r.to_json(|ts| opts.timestamp_json(ts))
+            })?
+        }
+        &Type::TSTZ_RANGE_ARRAY => {
+            get_column_json_array_with(row, index, |r: Range<DateTime<Utc>>| {
+                r.to_json(|ts| opts.timestamptz_json(ts))
+            })?
+        }
+        &Type::DATE_RANGE_ARRAY => get_column_json_array_with(row, index, date_range_json)?,
+        &Type::INTERVAL_ARRAY => {
+            get_column_json_array_with(row, index, |i: Interval| i.to_iso8601())?
+        }
+        ty if opts.unsupported_types_as_text && FallbackText::reads(ty) => match ty.kind() {
+            Kind::Array(_) => get_column_json_array_with(row, index, |t: FallbackText| t.0)?,
+            _ => get_column_json_value_with(row, index, |t: FallbackText| t.0)?,
+        },
         other => {
+            let hint = if FallbackText::reads(other) {
+                "set unsupported_types_as_text to read it as text"
+            } else {
+                "cast it with ::text to read it as text"
+            };
             bail!(
-                "Could not convert column '{}' to json - unsupported column type '{}'",
+                "Could not convert column '{}' to json - unsupported column type '{}', {}",
                 column.name(),
-                other
+                other,
+                hint
             );
         }
     };
     Ok(value)
 }
 
-fn row_to_json_map(row: &Row) -> Result<JsonValue, anyhow::Error> {
+fn hstore_json(map: HashMap<String, Option<String>>) -> JsonValue {
+    map.into_iter()
+        .map(|(key, value)| (key, value.map(JsonValue::String).unwrap_or(JsonValue::Null)))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+fn composite_column_json(
+    row: &Row,
+    index: usize,
+    opts: &JsonOptions,
+) -> Result<JsonValue, anyhow::Error> {
+    match row.try_get::<_, Option<composite::Fields>>(index)? {
+        Some(fields) => composite_json(row.columns()[index].type_(), fields, opts),
+        None => Ok(JsonValue::Null),
+    }
+}
+
+/// Convert a composite value to a JSON object keyed by field name.
+///
+/// Anonymous records have no field names, so they are converted to an array
+/// of their field values.
+fn composite_json(
+    ty: &Type,
+    fields: composite::Fields,
+    opts: &JsonOptions,
+) -> Result<JsonValue, anyhow::Error> {
+    let values = fields.0.into_iter().map(|field| match &field.type_ {
+        Some(ty) => field_json(ty, field.raw, opts),
+        None => bail!("unsupported composite field type with oid {}", field.oid),
+    });
+
+    match ty.kind() {
+        Kind::Composite(declared) => {
+            let map = declared
+                .iter()
+                .zip(values)
+                .map(|(decl, value)| Ok((decl.name().to_string(), value?)))
+                .collect::<Result<serde_json::Map<_, _>, anyhow::Error>>()?;
+            Ok(JsonValue::Object(map))
+        }
+        _ => Ok(JsonValue::Array(values.collect::<Result<_, _>>()?)),
+    }
+}
+
+/// Convert a single field of a composite value.
+///
+/// Supports the scalar types of [`row_column_to_json`] and nested
+/// composites, but not arrays.
+fn field_json(
+    ty: &Type,
+    raw: Option<&[u8]>,
+    opts: &JsonOptions,
+) -> Result<JsonValue, anyhow::Error> {
+    fn decode<'a, T: FromSql<'a>>(ty: &Type, raw: &'a [u8]) -> Result<T, anyhow::Error> {
+        T::from_sql(ty, raw).map_err(|err| anyhow::anyhow!("invalid {ty} value: {err}"))
+    }
+
+    let Some(raw) = raw else {
+        return Ok(JsonValue::Null);
+    };
+
+    match ty.kind() {
+        Kind::Enum(_) => return Ok(decode::<EnumLabel>(ty, raw)?.0.into()),
+        Kind::Composite(_) => return composite_json(ty, decode(ty, raw)?, opts),
+        _ => {}
+    }
+
+    let value: JsonValue = match ty {
+        &Type::BOOL => decode::<bool>(ty, raw)?.into(),
+        &Type::INT2 => decode::<i16>(ty, raw)?.into(),
+        &Type::INT4 => decode::<i32>(ty, raw)?.into(),
+        &Type::INT8 => decode::<i64>(ty, raw)?.into(),
+        &Type::FLOAT4 => decode::<f32>(ty, raw)?.into(),
+        &Type::FLOAT8 => decode::<f64>(ty, raw)?.into(),
+        &Type::CHAR | &Type::VARCHAR | &Type::TEXT => decode::<String>(ty, raw)?.into(),
+        &Type::JSON | &Type::JSONB if opts.json_encoding == JsonEncoding::Text => {
+            decode::<JsonText>(ty, raw)?.0.into()
+        }
+        &Type::JSON | &Type::JSONB => decode::<JsonValue>(ty, raw)?,
+        &Type::VOID => JsonValue::Null,
+        &Type::UNKNOWN => decode::<UnknownText>(ty, raw)?.0.into(),
+        &Type::NUMERIC => opts.numeric_json(decode(ty, raw)?),
+        &Type::UUID => decode::<uuid::Uuid>(ty, raw)?.to_string().into(),
+        &Type::TIMESTAMPTZ => opts.timestamptz_json(decode(ty, raw)?),
+        &Type::TIMESTAMP => opts.timestamp_json(decode(ty, raw)?),
+        &Type::DATE => datetime::format_date(decode(ty, raw)?).into(),
+        &Type::TIME => datetime::format_time(decode(ty, raw)?).into(),
+        &Type::BYTEA => opts.bytea_json(raw),
+        &Type::INET | &Type::CIDR => decode::<network::Inet>(ty, raw)?.to_text().into(),
+        &Type::MACADDR => decode::<network::MacAddr>(ty, raw)?.to_text().into(),
+        &Type::INTERVAL => decode::<Interval>(ty, raw)?.to_iso8601().into(),
+        &Type::INT4_RANGE => decode::<Range<i32>>(ty, raw)?.to_json(JsonValue::from),
+        &Type::INT8_RANGE => decode::<Range<i64>>(ty, raw)?.to_json(JsonValue::from),
+        &Type::NUM_RANGE => decode::<Range<Numeric>>(ty, raw)?.to_json(|n| opts.numeric_json(n)),
+        &Type::TS_RANGE => {
+            decode::<Range<NaiveDateTime>>(ty, raw)?.to_json(|ts| opts.timestamp_json(ts))
+        }
+        &Type::TSTZ_RANGE => {
+            decode::<Range<DateTime<Utc>>>(ty, raw)?.to_json(|ts| opts.timestamptz_json(ts))
+        }
+        &Type::DATE_RANGE => date_range_json(decode(ty, raw)?),
+        &Type::RECORD => composite_json(ty, decode(ty, raw)?, opts)?,
+        ty if opts.unsupported_types_as_text && FallbackText::accepts(ty) => {
+            decode::<FallbackText>(ty, raw)?.0.into()
+        }
+        other => bail!("unsupported composite field type '{other}'"),
+    };
+    Ok(value)
+}
+
+/// Map a Postgres type to the backend-neutral column type.
+///
+/// Must stay in sync with the values produced by [`row_column_to_json`].
+fn column_type(ty: &Type) -> ColumnType {
+    if let Kind::Enum(_) = ty.kind() {
+        return ColumnType::Text;
+    }
+
+    match ty {
+        &Type::BOOL => ColumnType::Bool,
+        &Type::INT2 => ColumnType::Int16,
+        &Type::INT4 => ColumnType::Int32,
+        &Type::INT8 => ColumnType::Int64,
+        &Type::FLOAT4 => ColumnType::Float32,
+        &Type::FLOAT8 => ColumnType::Float64,
+        &Type::TIMESTAMP => ColumnType::Timestamp,
+        &Type::TIMESTAMPTZ => ColumnType::TimestampTz,
+        &Type::CHAR
+        | &Type::VARCHAR
+        | &Type::TEXT
+        | &Type::NUMERIC
+        | &Type::UUID
+        | &Type::DATE
+        | &Type::TIME
+        | &Type::BYTEA
+        | &Type::INET
+        | &Type::CIDR
+        | &Type::MACADDR
+        | &Type::INTERVAL
+        | &Type::UNKNOWN => ColumnType::Text,
+        _ => ColumnType::Json,
+    }
+}
+
+fn row_to_json_map(row: &Row, opts: &JsonOptions) -> Result<JsonValue, anyhow::Error> {
     let mut map = serde_json::Map::new();
 
     for (index, col) in row.columns().iter().enumerate() {
         let name = col.name();
-        let value = row_column_to_json(row, col, index)?;
+        let value = row_column_to_json(row, col, index, opts)?;
+        if opts.omit_nulls && value.is_null() {
+            continue;
+        }
         map.insert(name.to_string(), value);
     }
 
     Ok(JsonValue::Object(map))
 }
 
-fn row_to_json_columns(row: &Row) -> Result<Vec<JsonValue>, anyhow::Error> {
+fn row_to_json_columns(row: &Row, opts: &JsonOptions) -> Result<Vec<JsonValue>, anyhow::Error> {
     let columns = row.columns();
     let mut vals = Vec::with_capacity(columns.len());
 
     for (index, col) in row.columns().iter().enumerate() {
-        let value = row_column_to_json(row, col, index)?;
+        let value = row_column_to_json(row, col, index, opts)?;
         vals.push(value);
     }
 
@@ -287,6 +1732,20 @@ where
     }
 }
 
+/// Like [`get_column_json_value`], for types that need a custom conversion.
+fn get_column_json_value_with<'a, T, V>(
+    row: &'a Row,
+    index: usize,
+    convert: impl Fn(T) -> V,
+) -> Result<JsonValue, tokio_postgres::Error>
+where
+    T: FromSql<'a>,
+    JsonValue: From<V>,
+{
+    let value = row.try_get::<_, Option<T>>(index)?;
+    Ok(value.map(|v| convert(v).into()).unwrap_or(JsonValue::Null))
+}
+
 // fn get_column_json_array_opt<'a, T>(
 //     row: &'a tokio_postgres::Row,
 //     index: usize,
@@ -327,3 +1786,54 @@ where
         .collect();
     Ok(JsonValue::Array(json_items))
 }
+
+/// Like [`get_column_json_array_as_value`], for types that need a custom
+/// conversion.
+fn get_column_json_array_with<'a, T, V>(
+    row: &'a Row,
+    index: usize,
+    convert: impl Fn(T) -> V,
+) -> Result<JsonValue, tokio_postgres::Error>
+where
+    T: FromSql<'a>,
+    JsonValue: From<V>,
+{
+    let Some(items) = row.try_get::<_, Option<Vec<Option<T>>>>(index)? else {
+        return Ok(JsonValue::Null);
+    };
+
+    let json_items = items
+        .into_iter()
+        .map(|item| item.map(|v| convert(v).into()).unwrap_or(JsonValue::Null))
+        .collect();
+    Ok(JsonValue::Array(json_items))
+}
+
+fn date_range_json(range: Range<NaiveDate>) -> JsonValue {
+    range.to_json(|d| datetime::format_date(d).into())
+}
+
+/// Serialize a `daterange` as `{"from": ..., "to": ..., "inclusive_end": ...}`
+/// with ISO dates.
+///
+/// Unbounded ends are represented as `null` dates.
+/// Empty ranges have `null` dates and an additional `"empty": true`.
+fn date_range_bounds_json(row: &Row, index: usize) -> Result<JsonValue, tokio_postgres::Error> {
+    let Some(range) = row.try_get::<_, Option<Range<NaiveDate>>>(index)? else {
+        return Ok(JsonValue::Null);
+    };
+    if range.empty {
+        return Ok(serde_json::json!({
+            "from": null,
+            "to": null,
+            "inclusive_end": false,
+            "empty": true,
+        }));
+    }
+
+    Ok(serde_json::json!({
+        "from": range.lower.map(|d| d.to_string()),
+        "to": range.upper.map(|d| d.to_string()),
+        "inclusive_end": range.upper_inclusive,
+    }))
+}
@@ -0,0 +1,160 @@
+//! Streaming notifications with LISTEN/NOTIFY.
+
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context as TaskContext, Poll},
+};
+
+use daprox_core::{ArgumentError, DatabaseError, DatabaseErrorKind};
+use futures::Stream;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_postgres::Client;
+
+use crate::{database_error, quote_ident, start_connection, PostgresProx};
+
+/// Maximum number of received notifications that were not consumed yet.
+const NOTIFICATION_BUFFER: usize = 1024;
+
+/// A notification sent with `NOTIFY` or `pg_notify`.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct Notification {
+    pub channel: String,
+    pub payload: String,
+    /// Process id of the backend that sent the notification.
+    pub process_id: i32,
+}
+
+/// The stream fell behind by more than [`NOTIFICATION_BUFFER`]
+/// notifications, so notifications were dropped.
+#[derive(Debug)]
+pub struct NotificationOverflow;
+
+impl std::fmt::Display for NotificationOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "More than {NOTIFICATION_BUFFER} notifications were not received in time"
+        )
+    }
+}
+
+impl std::error::Error for NotificationOverflow {}
+
+/// Notifications received on a dedicated connection.
+///
+/// Ends with an error if the connection is lost, or with a
+/// [`NotificationOverflow`] if notifications are not consumed fast enough.
+/// Dropping the stream stops listening and closes the connection.
+pub struct NotificationStream {
+    /// `None` once the connection was lost.
+    client: Option<Client>,
+    channel: String,
+    notifications: mpsc::Receiver<Notification>,
+    overflowed: Arc<AtomicBool>,
+}
+
+/// Forwards the notifications of a connection to a [`NotificationStream`].
+pub(crate) struct NotificationSender {
+    sender: mpsc::Sender<Notification>,
+    overflowed: Arc<AtomicBool>,
+}
+
+impl NotificationSender {
+    /// Returns `false` once the stream was dropped or fell behind, and no
+    /// more notifications should be sent.
+    pub(crate) fn send(&self, notification: Notification) -> bool {
+        match self.sender.try_send(notification) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.overflowed.store(true, Ordering::Relaxed);
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+impl Stream for NotificationStream {
+    type Item = Result<Notification, anyhow::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        if self.client.is_none() {
+            return Poll::Ready(None);
+        }
+        match self.notifications.poll_recv(cx) {
+            Poll::Ready(Some(notification)) => Poll::Ready(Some(Ok(notification))),
+            // The sender is only dropped once the connection is closed, or
+            // the stream fell behind.
+            Poll::Ready(None) if self.overflowed.load(Ordering::Relaxed) => {
+                self.client = None;
+                Poll::Ready(Some(Err(NotificationOverflow.into())))
+            }
+            Poll::Ready(None) => {
+                self.client = None;
+                Poll::Ready(Some(Err(DatabaseError {
+                    message: "Database connection lost".to_string(),
+                    kind: DatabaseErrorKind::Unavailable,
+                    ..Default::default()
+                }
+                .into())))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for NotificationStream {
+    fn drop(&mut self) {
+        let Some(client) = self.client.take() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let unlisten = format!("UNLISTEN {}", quote_ident(&self.channel));
+        // The connection is closed once the client is dropped.
+        runtime.spawn(async move {
+            if let Err(err) = client.batch_execute(&unlisten).await {
+                tracing::debug!("Could not stop listening: {}", err);
+            }
+        });
+    }
+}
+
+impl PostgresProx {
+    /// Run `LISTEN channel` on a new connection that is not managed by the
+    /// pool, and stream the notifications sent to the channel.
+    pub async fn listen(
+        &self,
+        uri: &str,
+        channel: &str,
+    ) -> Result<NotificationStream, anyhow::Error> {
+        if channel.is_empty() {
+            return Err(ArgumentError("channel must not be empty".to_string()).into());
+        }
+        let (client, _notices, notifications, _canceller) =
+            start_connection(uri, &self.config).await?;
+        let (sender, receiver) = mpsc::channel(NOTIFICATION_BUFFER);
+        let overflowed = Arc::new(AtomicBool::new(false));
+        *notifications.lock().unwrap() = Some(NotificationSender {
+            sender,
+            overflowed: overflowed.clone(),
+        });
+
+        client
+            .batch_execute(&format!("LISTEN {}", quote_ident(channel)))
+            .await
+            .map_err(database_error)?;
+        tracing::debug!(%channel, "Listening for notifications");
+        Ok(NotificationStream {
+            client: Some(client),
+            channel: channel.to_string(),
+            notifications: receiver,
+            overflowed,
+        })
+    }
+}
@@ -0,0 +1,126 @@
+//! Rewriting of named query parameters to positional ones.
+
+use std::fmt::Write as _;
+
+/// Replace `:name` placeholders with positional `$N` parameters.
+///
+/// Returns the rewritten query and the parameter names, ordered by their
+/// position. Repeated names are bound to the same parameter.
+///
+/// Placeholders in string literals, quoted identifiers, dollar-quoted
+/// strings and comments are left as is, as are `::` casts.
+pub(crate) fn rewrite_named_params(sql: &str) -> (String, Vec<&str>) {
+    let bytes = sql.as_bytes();
+    let mut out = String::with_capacity(sql.len());
+    let mut names = Vec::<&str>::new();
+    // Start of the part of `sql` that was not yet copied to `out`.
+    let mut copied = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        i = match (bytes[i], bytes.get(i + 1)) {
+            (b'\'', _) => {
+                let escapes = i > 0
+                    && matches!(bytes[i - 1], b'E' | b'e')
+                    && (i < 2 || !is_ident_byte(bytes[i - 2]));
+                skip_quoted(bytes, i, b'\'', escapes)
+            }
+            (b'"', _) => skip_quoted(bytes, i, b'"', false),
+            (b'-', Some(b'-')) => match sql[i..].find('\n') {
+                Some(end) => i + end + 1,
+                None => bytes.len(),
+            },
+            (b'/', Some(b'*')) => skip_block_comment(bytes, i),
+            (b'$', _) => skip_dollar_quoted(sql, i),
+            (b':', Some(b':')) => i + 2,
+            (b':', Some(&next)) if next.is_ascii_alphabetic() || next == b'_' => {
+                let start = i + 1;
+                let end = start
+                    + bytes[start..]
+                        .iter()
+                        .take_while(|b| is_ident_byte(**b))
+                        .count();
+                let name = &sql[start..end];
+                let position = match names.iter().position(|n| *n == name) {
+                    Some(index) => index + 1,
+                    None => {
+                        names.push(name);
+                        names.len()
+                    }
+                };
+                out.push_str(&sql[copied..i]);
+                write!(out, "${position}").unwrap();
+                copied = end;
+                end
+            }
+            _ => i + 1,
+        };
+    }
+
+    out.push_str(&sql[copied..]);
+    (out, names)
+}
+
+fn is_ident_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/// Skip a quoted string or identifier starting at `start`.
+///
+/// Doubled quotes are handled as a closed and reopened quote.
+/// Returns the index after the closing quote.
+fn skip_quoted(bytes: &[u8], start: usize, quote: u8, escapes: bool) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if escapes => i += 2,
+            b if b == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Skip a (possibly nested) block comment starting at `start`.
+fn skip_block_comment(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (b'/', Some(b'*')) => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', Some(b'/')) => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Skip a dollar-quoted string like `$tag$...$tag$` starting at `start`.
+///
+/// Other uses of `$`, like positional parameters, are skipped as a single
+/// character.
+fn skip_dollar_quoted(sql: &str, start: usize) -> usize {
+    let rest = &sql[start + 1..];
+    let tag_len = rest.bytes().take_while(|b| is_ident_byte(*b)).count();
+    let is_tag = rest.as_bytes().get(tag_len) == Some(&b'$')
+        && !matches!(rest.as_bytes().first(), Some(b) if b.is_ascii_digit());
+    if !is_tag {
+        return start + 1;
+    }
+
+    let delimiter = &sql[start..start + tag_len + 2];
+    let body = start + delimiter.len();
+    match sql[body..].find(delimiter) {
+        Some(end) => body + end + delimiter.len(),
+        None => sql.len(),
+    }
+}
@@ -0,0 +1,93 @@
+//! Decoding of the Postgres network address types `inet`, `cidr` and
+//! `macaddr`.
+
+use std::{
+    error::Error,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use postgres_types::{FromSql, Type};
+
+/// Address family of IPv4 values in the binary format.
+const PGSQL_AF_INET: u8 = 2;
+/// Address family of IPv6 values in the binary format.
+const PGSQL_AF_INET6: u8 = 3;
+
+/// A Postgres `inet` or `cidr` value.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct Inet {
+    pub addr: IpAddr,
+    /// Length of the network prefix.
+    pub bits: u8,
+    pub is_cidr: bool,
+}
+
+impl Inet {
+    /// The text representation, as produced by Postgres.
+    ///
+    /// The prefix length is omitted for `inet` host addresses.
+    pub fn to_text(&self) -> String {
+        let max_bits = match self.addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if self.is_cidr || self.bits != max_bits {
+            format!("{}/{}", self.addr, self.bits)
+        } else {
+            self.addr.to_string()
+        }
+    }
+}
+
+impl<'a> FromSql<'a> for Inet {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let [family, bits, is_cidr, len, addr @ ..] = raw else {
+            return Err("invalid inet value: truncated header".into());
+        };
+        if addr.len() != *len as usize {
+            return Err("invalid inet value: address length mismatch".into());
+        }
+
+        let addr = match *family {
+            PGSQL_AF_INET => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(addr)?)),
+            PGSQL_AF_INET6 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(addr)?)),
+            other => return Err(format!("invalid inet address family: {other}").into()),
+        };
+        Ok(Self {
+            addr,
+            bits: *bits,
+            is_cidr: *is_cidr != 0,
+        })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(*ty, Type::INET | Type::CIDR)
+    }
+}
+
+/// A Postgres `macaddr` value.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    /// The text representation, as produced by Postgres, like
+    /// `08:00:2b:01:02:03`.
+    pub fn to_text(&self) -> String {
+        self.0
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+}
+
+impl<'a> FromSql<'a> for MacAddr {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let bytes = <[u8; 6]>::try_from(raw).map_err(|_| "invalid macaddr value")?;
+        Ok(Self(bytes))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::MACADDR
+    }
+}
@@ -0,0 +1,258 @@
+//! Decoding and encoding of Postgres `numeric` values.
+//!
+//! Values are converted between the binary format and their exact decimal
+//! text, because `numeric` can exceed the range and precision of native
+//! number types, and can hold the special values `NaN` and `±Infinity`.
+
+use std::error::Error;
+
+use bytes::{BufMut, BytesMut};
+use postgres_types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+
+const NUMERIC_POS: u16 = 0x0000;
+const NUMERIC_NEG: u16 = 0x4000;
+const NUMERIC_NAN: u16 = 0xC000;
+const NUMERIC_PINF: u16 = 0xD000;
+const NUMERIC_NINF: u16 = 0xF000;
+
+/// Number of decimal digits per base-10000 digit.
+const DEC_DIGITS: usize = 4;
+
+/// Maximum number of decimal digits before or after the decimal point.
+const MAX_DIGITS: usize = 16383;
+
+/// A Postgres `numeric` value.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub(crate) enum Numeric {
+    NaN,
+    Infinity,
+    NegativeInfinity,
+    /// A finite value in decimal notation, like `-12.50`.
+    Value(String),
+}
+
+impl Numeric {
+    /// Parse a decimal like `-12.50` or `1.5e-3`, or one of the special
+    /// values.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        match text {
+            "NaN" => return Ok(Self::NaN),
+            "Infinity" => return Ok(Self::Infinity),
+            "-Infinity" => return Ok(Self::NegativeInfinity),
+            _ => {}
+        }
+        let invalid = || format!("invalid numeric value: {text}").into();
+        let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => {
+                (mantissa, exponent.parse::<i32>().map_err(|_| invalid())?)
+            }
+            None => (text, 0),
+        };
+        let unsigned = mantissa.strip_prefix('-').unwrap_or(mantissa);
+        let (int, frac) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if int.is_empty() || !is_digits(int) || !is_digits(frac) {
+            return Err(invalid());
+        }
+        if exponent == 0 {
+            return Ok(Self::Value(text.to_string()));
+        }
+
+        // Move the decimal point by the exponent.
+        let digits = format!("{int}{frac}");
+        let point = int.len() as i64 + exponent as i64;
+        if point.unsigned_abs() > MAX_DIGITS as u64 || digits.len() > MAX_DIGITS {
+            return Err(invalid());
+        }
+        let mut out = String::new();
+        if unsigned.len() != mantissa.len() {
+            out.push('-');
+        }
+        if point <= 0 {
+            out.push_str("0.");
+            out.push_str(&"0".repeat(point.unsigned_abs() as usize));
+            out.push_str(&digits);
+        } else {
+            let point = point as usize;
+            let padded = format!("{digits:0<point$}");
+            let (int, frac) = padded.split_at(point);
+            out.push_str(int.trim_start_matches('0'));
+            if out.is_empty() || out == "-" {
+                out.push('0');
+            }
+            if !frac.is_empty() {
+                out.push('.');
+                out.push_str(frac);
+            }
+        }
+        Ok(Self::Value(out))
+    }
+
+    /// The text representation, as produced by Postgres.
+    pub fn to_text(&self) -> String {
+        match self {
+            Self::NaN => "NaN".to_string(),
+            Self::Infinity => "Infinity".to_string(),
+            Self::NegativeInfinity => "-Infinity".to_string(),
+            Self::Value(v) => v.clone(),
+        }
+    }
+}
+
+impl<'a> FromSql<'a> for Numeric {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let mut header = [0u16; 4];
+        for (i, value) in header.iter_mut().enumerate() {
+            *value = read_u16(raw, i)?;
+        }
+        let [ndigits, weight, sign, dscale] = header;
+        let ndigits = ndigits as usize;
+        let weight = weight as i16 as isize;
+        let dscale = dscale as usize;
+
+        let negative = match sign {
+            NUMERIC_POS => false,
+            NUMERIC_NEG => true,
+            NUMERIC_NAN => return Ok(Self::NaN),
+            NUMERIC_PINF => return Ok(Self::Infinity),
+            NUMERIC_NINF => return Ok(Self::NegativeInfinity),
+            other => return Err(format!("invalid numeric sign: {other:#x}").into()),
+        };
+
+        let digits = (0..ndigits)
+            .map(|i| read_u16(raw, 4 + i))
+            .collect::<Result<Vec<_>, _>>()?;
+        let digit = |i: isize| -> u16 {
+            usize::try_from(i)
+                .ok()
+                .and_then(|i| digits.get(i).copied())
+                .unwrap_or(0)
+        };
+
+        let mut out = String::new();
+        if negative {
+            out.push('-');
+        }
+
+        // Integer part.
+        if weight < 0 {
+            out.push('0');
+        } else {
+            out.push_str(&digit(0).to_string());
+            for i in 1..=weight {
+                out.push_str(&format!("{:04}", digit(i)));
+            }
+        }
+
+        // Fractional part, padded or truncated to the display scale.
+        if dscale > 0 {
+            let mut frac = String::with_capacity(dscale + DEC_DIGITS);
+            let mut i = weight + 1;
+            while frac.len() < dscale {
+                frac.push_str(&format!("{:04}", digit(i)));
+                i += 1;
+            }
+            frac.truncate(dscale);
+            out.push('.');
+            out.push_str(&frac);
+        }
+
+        Ok(Self::Value(out))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty == &Type::NUMERIC
+    }
+}
+
+impl ToSql for Numeric {
+    fn to_sql(
+        &self,
+        _ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        let value = match self {
+            Self::NaN => return write_header(out, 0, 0, NUMERIC_NAN, 0),
+            Self::Infinity => return write_header(out, 0, 0, NUMERIC_PINF, 0),
+            Self::NegativeInfinity => return write_header(out, 0, 0, NUMERIC_NINF, 0),
+            Self::Value(v) => v,
+        };
+        let (sign, unsigned) = match value.strip_prefix('-') {
+            Some(unsigned) => (NUMERIC_NEG, unsigned),
+            None => (NUMERIC_POS, value.as_str()),
+        };
+        let (int, frac) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        if int.len() > MAX_DIGITS || frac.len() > MAX_DIGITS {
+            return Err(format!("numeric value {value} is out of range").into());
+        }
+        let dscale = frac.len();
+
+        // Align the digits to groups of four around the decimal point.
+        let int = int.trim_start_matches('0');
+        let lead = (DEC_DIGITS - int.len() % DEC_DIGITS) % DEC_DIGITS;
+        let trail = (DEC_DIGITS - frac.len() % DEC_DIGITS) % DEC_DIGITS;
+        let padded = format!("{}{int}{frac}{}", "0".repeat(lead), "0".repeat(trail));
+        let mut digits = padded
+            .as_bytes()
+            .chunks(DEC_DIGITS)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .fold(0u16, |acc, b| acc * 10 + u16::from(b - b'0'))
+            })
+            .collect::<Vec<_>>();
+        let mut weight = ((lead + int.len()) / DEC_DIGITS) as isize - 1;
+
+        // Leading and trailing zero groups are implied by the weight.
+        let leading = digits.iter().take_while(|d| **d == 0).count();
+        digits.drain(..leading);
+        weight -= leading as isize;
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        let sign = if digits.is_empty() {
+            weight = 0;
+            NUMERIC_POS
+        } else {
+            sign
+        };
+
+        write_header(
+            out,
+            digits.len() as u16,
+            weight as i16 as u16,
+            sign,
+            dscale as u16,
+        )?;
+        for digit in digits {
+            out.put_u16(digit);
+        }
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty == &Type::NUMERIC
+    }
+
+    to_sql_checked!();
+}
+
+fn write_header(
+    out: &mut BytesMut,
+    ndigits: u16,
+    weight: u16,
+    sign: u16,
+    dscale: u16,
+) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+    for value in [ndigits, weight, sign, dscale] {
+        out.put_u16(value);
+    }
+    Ok(IsNull::No)
+}
+
+fn read_u16(raw: &[u8], index: usize) -> Result<u16, Box<dyn Error + Sync + Send>> {
+    let offset = index * 2;
+    raw.get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| "invalid numeric value: unexpected end of input".into())
+}
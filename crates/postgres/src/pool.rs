@@ -0,0 +1,253 @@
+//! Pooling of database connections.
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+use daprox_core::{DatabaseError, DatabaseErrorKind};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{
+    cancel::CancelGuard, start_connection, statements::StatementCache, Connection, PostgresConfig,
+};
+
+/// Gauge of the connections that are currently in use, across all pools.
+const ACTIVE_CONNECTIONS_GAUGE: &str = "daprox_pool_connections_active";
+
+/// Interval in which idle connections are validated and replenished.
+/// Shorter if the idle timeout is.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Connections to a single database.
+pub(crate) struct Pool {
+    uri: String,
+    config: PostgresConfig,
+    /// Limits the number of open connections.
+    slots: Arc<Semaphore>,
+    /// Connections that are ready to be reused, most recently used last.
+    idle: Mutex<Vec<IdleConnection>>,
+}
+
+/// A connection that is ready to be reused.
+struct IdleConnection {
+    conn: Connection,
+    since: Instant,
+}
+
+impl Pool {
+    pub fn new(uri: &str, config: &PostgresConfig) -> Arc<Self> {
+        let size = config
+            .pool_sizes
+            .get(uri)
+            .copied()
+            .unwrap_or(config.pool_size)
+            .max(1);
+        let pool = Arc::new(Self {
+            uri: uri.to_string(),
+            config: config.clone(),
+            slots: Arc::new(Semaphore::new(size)),
+            idle: Mutex::new(Vec::new()),
+        });
+
+        let idle_timeout = config.idle_timeout_ms.map(Duration::from_millis);
+        if config.min_idle > 0 || idle_timeout.is_some() {
+            let min_idle = config.min_idle.min(size);
+            tokio::spawn(maintain(Arc::downgrade(&pool), min_idle, idle_timeout));
+        }
+        pool
+    }
+
+    /// Get a connection, waiting for one to become available if the pool is
+    /// exhausted.
+    ///
+    /// Fails with an unavailable error if no connection becomes available
+    /// within [`PostgresConfig::acquire_timeout_ms`].
+    pub async fn get(self: &Arc<Self>) -> Result<PooledConnection, anyhow::Error> {
+        let acquire = self.slots.clone().acquire_owned();
+        let permit = match self.config.acquire_timeout_ms {
+            Some(ms) => tokio::time::timeout(Duration::from_millis(ms), acquire)
+                .await
+                .map_err(|_| DatabaseError {
+                    message: format!(
+                        "Timed out after {ms}ms waiting for a free connection to the database"
+                    ),
+                    kind: DatabaseErrorKind::Unavailable,
+                    ..Default::default()
+                })??,
+            None => acquire.await?,
+        };
+        let conn = match self.take_idle() {
+            Some(conn) => conn,
+            None => self.open().await?,
+        };
+        metrics::increment_gauge!(ACTIVE_CONNECTIONS_GAUGE, 1.0);
+
+        Ok(PooledConnection {
+            conn: Some(conn),
+            pool: self.clone(),
+            discard: Arc::default(),
+            _permit: permit,
+        })
+    }
+
+    /// Close idle connections and stop handing out new ones.
+    ///
+    /// Connections that are in use are closed when they are returned.
+    pub fn close(&self) {
+        self.slots.close();
+        self.idle.lock().unwrap().clear();
+    }
+
+    /// Take the most recently used idle connection.
+    ///
+    /// Connections that were closed in the meantime are discarded.
+    fn take_idle(&self) -> Option<Connection> {
+        let mut idle = self.idle.lock().unwrap();
+        while let Some(IdleConnection { conn, .. }) = idle.pop() {
+            if !conn.client.is_closed() {
+                return Some(conn);
+            }
+        }
+        None
+    }
+
+    /// Return a connection to the idle list.
+    fn put_idle(&self, conn: Connection) {
+        self.idle.lock().unwrap().push(IdleConnection {
+            conn,
+            since: Instant::now(),
+        });
+    }
+
+    /// Close connections that were idle for longer than `timeout`.
+    fn reap_idle(&self, timeout: Duration) {
+        let mut idle = self.idle.lock().unwrap();
+        let before = idle.len();
+        idle.retain(|idle| idle.since.elapsed() < timeout);
+        let reaped = before - idle.len();
+        if reaped > 0 {
+            tracing::debug!("Closed {} idle connections", reaped);
+        }
+    }
+
+    async fn open(&self) -> Result<Connection, anyhow::Error> {
+        let (client, notices, _notifications, canceller) =
+            start_connection(&self.uri, &self.config).await?;
+        Ok(Connection {
+            client,
+            statements: StatementCache::new(self.config.statement_cache_size),
+            notices,
+            canceller,
+        })
+    }
+
+    /// Check that idle connections still work, and discard broken ones.
+    async fn validate_idle(&self) {
+        let mut validated = Vec::new();
+        // Connections are taken out of the idle list while they are checked,
+        // so hold a slot for each to stay within the pool size.
+        while let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            let Some(idle) = self.idle.lock().unwrap().pop() else {
+                break;
+            };
+            if !idle.conn.client.is_closed() && idle.conn.client.simple_query("").await.is_ok() {
+                validated.push((idle, permit));
+            }
+        }
+
+        let mut idle = self.idle.lock().unwrap();
+        // Keep the previous order and idle times, with the most recently
+        // used connection last.
+        idle.extend(validated.into_iter().rev().map(|(idle, _permit)| idle));
+    }
+
+    /// Open connections until `min_idle` connections are idle.
+    async fn fill_idle(&self, min_idle: usize) -> Result<(), anyhow::Error> {
+        while self.idle.lock().unwrap().len() < min_idle {
+            let Ok(_permit) = self.slots.try_acquire() else {
+                break;
+            };
+            let conn = self.open().await?;
+            self.put_idle(conn);
+        }
+        Ok(())
+    }
+}
+
+/// Close expired idle connections and keep the others alive, until the pool
+/// is closed or dropped.
+async fn maintain(pool: Weak<Pool>, min_idle: usize, idle_timeout: Option<Duration>) {
+    let period = idle_timeout.map_or(MAINTENANCE_INTERVAL, |timeout| {
+        timeout.clamp(Duration::from_secs(1), MAINTENANCE_INTERVAL)
+    });
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let Some(pool) = pool.upgrade() else {
+            return;
+        };
+        if pool.slots.is_closed() {
+            return;
+        }
+        if let Some(timeout) = idle_timeout {
+            pool.reap_idle(timeout);
+        }
+        pool.validate_idle().await;
+        if let Err(err) = pool.fill_idle(min_idle).await {
+            tracing::warn!("Could not open idle connection: {}", err);
+        }
+    }
+}
+
+/// A connection borrowed from a [`Pool`].
+///
+/// Returned to the pool when dropped, unless it or the pool was closed.
+pub(crate) struct PooledConnection {
+    conn: Option<Connection>,
+    pool: Arc<Pool>,
+    /// Set if a query was cancelled, so the connection must not be reused.
+    discard: Arc<AtomicBool>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledConnection {
+    /// Guard that cancels the query running on the connection if it is
+    /// dropped before being disarmed.
+    pub fn cancel_guard(&self) -> CancelGuard {
+        let conn = self.conn.as_ref().unwrap();
+        CancelGuard::new(conn.canceller.clone(), self.discard.clone())
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conn.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        metrics::decrement_gauge!(ACTIVE_CONNECTIONS_GAUGE, 1.0);
+        if let Some(conn) = self.conn.take() {
+            // Stop collecting notices for the query that used the connection.
+            *conn.notices.lock().unwrap() = None;
+            let reusable = !self.discard.load(Ordering::Relaxed) && !conn.client.is_closed();
+            if reusable && !self.pool.slots.is_closed() {
+                self.pool.put_idle(conn);
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Decoding of Postgres range types.
+
+use std::error::Error;
+
+use postgres_types::{FromSql, Kind, Type};
+use serde_json::Value as JsonValue;
+
+const RANGE_EMPTY: u8 = 0x01;
+const RANGE_LOWER_INCLUSIVE: u8 = 0x02;
+const RANGE_UPPER_INCLUSIVE: u8 = 0x04;
+const RANGE_LOWER_INFINITE: u8 = 0x08;
+const RANGE_UPPER_INFINITE: u8 = 0x10;
+
+/// A value of a Postgres range type like `int4range` or `daterange`.
+///
+/// Unbounded ends are `None`.
+#[derive(Clone, Debug)]
+pub(crate) struct Range<T> {
+    pub lower: Option<T>,
+    pub upper: Option<T>,
+    pub lower_inclusive: bool,
+    pub upper_inclusive: bool,
+    pub empty: bool,
+}
+
+impl<T> Range<T> {
+    /// Serialize as `{"lower": ..., "upper": ..., "lower_inc": ..., "upper_inc": ...}`,
+    /// converting the bounds with `bound`.
+    ///
+    /// Unbounded ends are `null`.
+    /// Empty ranges have `null` bounds and an additional `"empty": true`.
+    pub fn to_json(self, bound: impl Fn(T) -> JsonValue) -> JsonValue {
+        let mut value = serde_json::json!({
+            "lower": self.lower.map(&bound),
+            "upper": self.upper.map(&bound),
+            "lower_inc": self.lower_inclusive,
+            "upper_inc": self.upper_inclusive,
+        });
+        if self.empty {
+            value["empty"] = JsonValue::Bool(true);
+        }
+        value
+    }
+}
+
+impl<'a, T: FromSql<'a>> FromSql<'a> for Range<T> {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let Kind::Range(element) = ty.kind() else {
+            return Err(format!("{ty} is not a range type").into());
+        };
+        let Some((&flags, mut buf)) = raw.split_first() else {
+            return Err("invalid range value: missing flags".into());
+        };
+
+        if flags & RANGE_EMPTY != 0 {
+            return Ok(Self {
+                lower: None,
+                upper: None,
+                lower_inclusive: false,
+                upper_inclusive: false,
+                empty: true,
+            });
+        }
+
+        let lower = if flags & RANGE_LOWER_INFINITE == 0 {
+            Some(read_bound(element, &mut buf)?)
+        } else {
+            None
+        };
+        let upper = if flags & RANGE_UPPER_INFINITE == 0 {
+            Some(read_bound(element, &mut buf)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            lower,
+            upper,
+            lower_inclusive: flags & RANGE_LOWER_INCLUSIVE != 0,
+            upper_inclusive: flags & RANGE_UPPER_INCLUSIVE != 0,
+            empty: false,
+        })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        match ty.kind() {
+            Kind::Range(element) => T::accepts(element),
+            _ => false,
+        }
+    }
+}
+
+/// Read a single length-prefixed range bound.
+fn read_bound<'a, T: FromSql<'a>>(
+    ty: &Type,
+    buf: &mut &'a [u8],
+) -> Result<T, Box<dyn Error + Sync + Send>> {
+    if buf.len() < 4 {
+        return Err("invalid range value: truncated bound".into());
+    }
+    let (len, rest) = buf.split_at(4);
+    let len = i32::from_be_bytes(len.try_into().unwrap());
+    let len = usize::try_from(len).map_err(|_| "invalid range value: null bound")?;
+    if rest.len() < len {
+        return Err("invalid range value: truncated bound".into());
+    }
+
+    let (value, rest) = rest.split_at(len);
+    *buf = rest;
+    T::from_sql(ty, value)
+}
@@ -0,0 +1,103 @@
+//! Introspection of database schemas.
+
+use crate::{database_error, PostgresProx};
+
+const SCHEMA_QUERY: &str = "\
+SELECT c.table_schema::text, c.table_name::text, t.table_type::text,
+       c.column_name::text, c.data_type::text, c.is_nullable = 'YES',
+       c.column_default::text
+FROM information_schema.columns c
+JOIN information_schema.tables t
+  ON t.table_schema = c.table_schema AND t.table_name = c.table_name
+WHERE CASE WHEN $1::text IS NULL
+           THEN c.table_schema NOT IN ('pg_catalog', 'information_schema')
+           ELSE c.table_schema = $1 END
+  AND ($2::text IS NULL OR c.table_name = $2)
+ORDER BY c.table_schema, c.table_name, c.ordinal_position";
+
+impl PostgresProx {
+    /// List the tables and views of a database, with their columns.
+    ///
+    /// System schemas are only included if requested explicitly with
+    /// `schema`. With `table`, only tables of that name are returned.
+    pub async fn schema(
+        &self,
+        db: &str,
+        schema: Option<&str>,
+        table: Option<&str>,
+    ) -> Result<Vec<TableSchema>, anyhow::Error> {
+        let conn = self.connection(db).await?;
+        let rows = conn
+            .client
+            .query(SCHEMA_QUERY, &[&schema, &table])
+            .await
+            .map_err(database_error)?;
+
+        let mut tables = Vec::<TableSchema>::new();
+        for row in rows {
+            let schema: String = row.get(0);
+            let name: String = row.get(1);
+            let column = ColumnSchema {
+                name: row.get(3),
+                data_type: row.get(4),
+                nullable: row.get(5),
+                default: row.get(6),
+            };
+
+            // Rows are ordered by table, so the columns of a table are
+            // adjacent.
+            match tables.last_mut() {
+                Some(t) if t.schema == schema && t.name == name => t.columns.push(column),
+                _ => tables.push(TableSchema {
+                    schema,
+                    name,
+                    kind: TableKind::from_table_type(row.get(2)),
+                    columns: vec![column],
+                }),
+            }
+        }
+
+        Ok(tables)
+    }
+}
+
+/// A table or view, as returned by [`PostgresProx::schema`].
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct TableSchema {
+    pub schema: String,
+    pub name: String,
+    pub kind: TableKind,
+    pub columns: Vec<ColumnSchema>,
+}
+
+#[derive(serde::Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TableKind {
+    Table,
+    View,
+    ForeignTable,
+    Other,
+}
+
+impl TableKind {
+    /// Parse the `table_type` of `information_schema.tables`.
+    fn from_table_type(value: &str) -> Self {
+        match value {
+            "BASE TABLE" | "LOCAL TEMPORARY" => Self::Table,
+            "VIEW" => Self::View,
+            "FOREIGN" => Self::ForeignTable,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// A column of a [`TableSchema`].
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct ColumnSchema {
+    pub name: String,
+    /// The SQL standard type name, like `integer` or `character varying`.
+    pub data_type: String,
+    pub nullable: bool,
+    /// The default expression, if any.
+    pub default: Option<String>,
+}
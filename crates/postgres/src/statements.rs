@@ -0,0 +1,102 @@
+//! Caching of prepared statements.
+
+use std::num::NonZeroUsize;
+
+use lru::LruCache;
+use postgres_types::{Kind, Type};
+use tokio_postgres::{error::SqlState, GenericClient, Statement};
+
+/// LRU cache of prepared statements, keyed by SQL text and the explicit
+/// parameter types.
+///
+/// Statements are bound to the connection that prepared them, so every
+/// connection has its own cache.
+pub(crate) struct StatementCache {
+    /// `None` if caching is disabled.
+    statements: Option<LruCache<(String, Vec<Type>), Statement>>,
+}
+
+impl StatementCache {
+    pub fn new(size: usize) -> Self {
+        Self {
+            statements: NonZeroUsize::new(size).map(LruCache::new),
+        }
+    }
+
+    /// Prepare a statement, reusing a previously prepared one for the same
+    /// SQL text and parameter types.
+    ///
+    /// `client` must belong to the connection that owns this cache.
+    /// Parameters without a type in `types`, or typed as
+    /// [`unspecified_type`], are inferred by Postgres.
+    /// With `untyped_as_text`, parameters whose type Postgres can not infer
+    /// are typed as `text`.
+    pub async fn prepare<C: GenericClient>(
+        &mut self,
+        client: &C,
+        sql: &str,
+        types: &[Type],
+        untyped_as_text: bool,
+    ) -> Result<Statement, tokio_postgres::Error> {
+        let key = (sql.to_string(), types.to_vec());
+        if let Some(statement) = self.statements.as_mut().and_then(|s| s.get(&key)) {
+            return Ok(statement.clone());
+        }
+
+        let statement = if untyped_as_text {
+            prepare_untyped_as_text(client, sql, types).await?
+        } else {
+            client.prepare_typed(sql, types).await?
+        };
+        if let Some(statements) = &mut self.statements {
+            statements.put(key, statement.clone());
+        }
+        Ok(statement)
+    }
+}
+
+/// Prepare a statement, typing parameters as `text` if Postgres can not
+/// infer their type.
+///
+/// Postgres only reports one such parameter at a time, so the statement is
+/// prepared again for each of them.
+async fn prepare_untyped_as_text<C: GenericClient>(
+    client: &C,
+    sql: &str,
+    types: &[Type],
+) -> Result<Statement, tokio_postgres::Error> {
+    let mut types = types.to_vec();
+
+    loop {
+        let err = match client.prepare_typed(sql, &types).await {
+            Ok(statement) => return Ok(statement),
+            Err(err) => err,
+        };
+        let Some(index) = indeterminate_param(&err) else {
+            return Err(err);
+        };
+        if types.get(index) == Some(&Type::TEXT) {
+            return Err(err);
+        }
+        if types.len() <= index {
+            types.resize_with(index + 1, unspecified_type);
+        }
+        types[index] = Type::TEXT;
+    }
+}
+
+/// A parameter type that lets Postgres infer the type, since its OID is 0.
+pub(crate) fn unspecified_type() -> Type {
+    Type::new("unspecified".to_string(), 0, Kind::Simple, String::new())
+}
+
+/// Zero-based index of the parameter from a
+/// "could not determine data type of parameter $N" error.
+fn indeterminate_param(err: &tokio_postgres::Error) -> Option<usize> {
+    let db = err.as_db_error()?;
+    if db.code() != &SqlState::INDETERMINATE_DATATYPE {
+        return None;
+    }
+    let number: usize = db.message().rsplit('$').next()?.trim().parse().ok()?;
+    number.checked_sub(1)
+}
@@ -0,0 +1,131 @@
+//! Running multiple queries in a single transaction.
+
+use daprox_core::{ArgumentError, SqlQuery};
+use serde_json::Value as JsonValue;
+use tokio_postgres::Transaction;
+
+use crate::{
+    args::{statement_params, QueryArgs},
+    database_error, fetch_cursors, row_to_json_map_with_cursors,
+    statements::StatementCache,
+    CursorRows, JsonOptions, PostgresProx,
+};
+
+/// Error of a transaction started with
+/// [`PostgresProx::query_json_maps_in_transaction`].
+#[derive(Debug)]
+pub struct TransactionError {
+    /// Index of the query that failed.
+    /// `None` if the transaction itself failed.
+    pub index: Option<usize>,
+    pub error: anyhow::Error,
+}
+
+impl TransactionError {
+    fn new(error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            index: None,
+            error: error.into(),
+        }
+    }
+}
+
+impl PostgresProx {
+    /// Run queries in a single transaction, and return the rows of each
+    /// query as JSON objects.
+    ///
+    /// The transaction is rolled back on the first failed query, and only
+    /// committed if all queries succeed.
+    /// All queries must use the same database. The transaction is read-only
+    /// if any of the queries sets `read_only_tx`.
+    pub async fn query_json_maps_in_transaction(
+        &self,
+        queries: &[SqlQuery],
+    ) -> Result<Vec<Vec<JsonValue>>, TransactionError> {
+        let Some(first) = queries.first() else {
+            return Ok(Vec::new());
+        };
+        if let Some(index) = queries.iter().position(|q| q.db != first.db) {
+            return Err(TransactionError {
+                index: Some(index),
+                error: ArgumentError(
+                    "all queries of a transaction must use the same database".to_string(),
+                )
+                .into(),
+            });
+        }
+        let read_only = queries.iter().any(|q| q.read_only_tx);
+
+        let mut pooled = self
+            .connection(&first.db)
+            .await
+            .map_err(TransactionError::new)?;
+        let conn = &mut *pooled;
+        let mut tx = conn
+            .client
+            .build_transaction()
+            .read_only(read_only)
+            .start()
+            .await
+            .map_err(|err| TransactionError::new(database_error(err)))?;
+
+        let mut results = Vec::with_capacity(queries.len());
+        for (index, query) in queries.iter().enumerate() {
+            match self
+                .query_in_transaction(&mut tx, &mut conn.statements, query)
+                .await
+            {
+                Ok(rows) => results.push(rows),
+                Err(error) => {
+                    if let Err(err) = tx.rollback().await {
+                        tracing::warn!("Could not roll back transaction: {}", err);
+                    }
+                    return Err(TransactionError {
+                        index: Some(index),
+                        error,
+                    });
+                }
+            }
+        }
+
+        tx.commit()
+            .await
+            .map_err(|err| TransactionError::new(database_error(err)))?;
+        Ok(results)
+    }
+
+    /// Run a single query in a transaction.
+    ///
+    /// `statements` must belong to the connection of the transaction.
+    async fn query_in_transaction(
+        &self,
+        tx: &mut Transaction<'_>,
+        statements: &mut StatementCache,
+        query: &SqlQuery,
+    ) -> Result<Vec<JsonValue>, anyhow::Error> {
+        query.check_statement_count()?;
+        let opts = JsonOptions::new(&self.config, query)?;
+        let query_args = QueryArgs::new(query, self.config.epoch_args_unit)?;
+        let types = query_args.param_types();
+        let QueryArgs { sql, args } = query_args;
+
+        let statement = statements
+            .prepare(&*tx, &sql, &types, self.config.untyped_args_as_text)
+            .await
+            .map_err(database_error)?;
+        let params = statement_params(&statement, &args, self.config.strict_args)?;
+        let rows = tx
+            .query(&statement, &params)
+            .await
+            .map_err(database_error)?;
+
+        let cursors = if query.fetch_cursors {
+            fetch_cursors(tx, &rows, &opts).await?
+        } else {
+            CursorRows::new()
+        };
+        rows.iter()
+            .map(|r| row_to_json_map_with_cursors(r, &cursors, &opts))
+            .collect()
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context as _};
+use async_trait::async_trait;
+use base64::Engine as _;
+use daprox_core::{ArgumentError, ColumnInfo, ColumnNames, ColumnType, SqlBackend, SqlQuery};
+use rusqlite::{
+    params_from_iter,
+    types::{Value, ValueRef},
+    Connection,
+};
+use serde_json::Value as JsonValue;
+
+/// URI of a private in-memory database.
+pub const MEMORY_URI: &str = "sqlite::memory:";
+
+type SharedConnection = Arc<Mutex<Connection>>;
+
+/// Backend for SQLite database files and in-memory databases.
+///
+/// Queries run on the blocking thread pool.
+/// Each database uses a single connection, which is kept open, so an
+/// in-memory database keeps its contents between queries.
+#[derive(Default)]
+pub struct SqliteProx {
+    /// Open connections, keyed by connection URI.
+    connections: Arc<Mutex<HashMap<String, SharedConnection>>>,
+}
+
+impl std::fmt::Debug for SqliteProx {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SqliteProx").finish_non_exhaustive()
+    }
+}
+
+/// Result of a query, with column types inferred from the values.
+struct QueryResult {
+    columns: Vec<ColumnInfo>,
+    rows: Vec<Vec<JsonValue>>,
+}
+
+impl SqliteProx {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if the URI refers to a SQLite database.
+    pub fn is_sqlite_uri(uri: &str) -> bool {
+        uri == MEMORY_URI || uri.starts_with("sqlite://")
+    }
+
+    async fn query_rows(&self, query: SqlQuery) -> Result<QueryResult, anyhow::Error> {
+        if query.fetch_cursors {
+            return Err(
+                ArgumentError("fetch_cursors is not supported for SQLite".to_string()).into(),
+            );
+        }
+        // Only the first statement of a query would be run.
+        if query.allow_multiple {
+            return Err(
+                ArgumentError("allow_multiple is not supported for SQLite".to_string()).into(),
+            );
+        }
+        if query.limit.is_some() || query.offset.is_some() {
+            return Err(
+                ArgumentError("limit and offset are not supported for SQLite".to_string()).into(),
+            );
+        }
+        query.check_statement_count()?;
+
+        let connections = self.connections.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = connection(&connections, &query.db)?;
+            let conn = conn.lock().unwrap();
+            if query.read_only_tx {
+                conn.pragma_update(None, "query_only", true)?;
+                let res = exec(&conn, &query);
+                conn.pragma_update(None, "query_only", false)?;
+                res
+            } else {
+                exec(&conn, &query)
+            }
+        })
+        .await?
+    }
+}
+
+/// Get the connection for a URI, opening the database if needed.
+fn connection(
+    connections: &Mutex<HashMap<String, SharedConnection>>,
+    uri: &str,
+) -> Result<SharedConnection, anyhow::Error> {
+    let mut connections = connections.lock().unwrap();
+    if let Some(conn) = connections.get(uri) {
+        return Ok(conn.clone());
+    }
+
+    let conn = match database_path(uri)? {
+        Some(path) => Connection::open(path)
+            .with_context(|| format!("Could not open SQLite database '{path}'"))?,
+        None => Connection::open_in_memory()?,
+    };
+    let conn = Arc::new(Mutex::new(conn));
+    connections.insert(uri.to_string(), conn.clone());
+    Ok(conn)
+}
+
+/// Extract the database file path from a connection URI.
+///
+/// Returns `None` for in-memory databases.
+fn database_path(uri: &str) -> Result<Option<&str>, anyhow::Error> {
+    if uri == MEMORY_URI {
+        return Ok(None);
+    }
+    match uri.strip_prefix("sqlite://") {
+        Some(":memory:") => Ok(None),
+        Some("") => bail!("SQLite URI '{uri}' has no database path"),
+        Some(path) => Ok(Some(path)),
+        None => bail!("Invalid SQLite URI '{uri}'"),
+    }
+}
+
+fn exec(conn: &Connection, query: &SqlQuery) -> Result<QueryResult, anyhow::Error> {
+    let mut stmt = conn.prepare(&query.query)?;
+
+    let names = stmt
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let declared = stmt
+        .columns()
+        .iter()
+        .map(|c| c.decl_type().and_then(declared_type))
+        .collect::<Vec<_>>();
+
+    let args = query
+        .args
+        .iter()
+        .flatten()
+        .map(json_to_value)
+        .collect::<Vec<_>>();
+
+    // SQLite is dynamically typed, so the values of a column may have
+    // different types than the column declaration.
+    let mut observed: Vec<Option<ColumnType>> = vec![None; names.len()];
+    let mut rows = Vec::new();
+    let limit = query.collect_limit();
+    let mut result = stmt.query(params_from_iter(args))?;
+    while rows.len() < limit {
+        let Some(row) = result.next()? else {
+            break;
+        };
+        let mut values = Vec::with_capacity(names.len());
+        for (index, name) in names.iter().enumerate() {
+            let value = row.get_ref(index)?;
+            if let Some(ty) = value_type(value) {
+                observed[index] = Some(match observed[index] {
+                    None => ty,
+                    Some(prev) => merge_types(prev, ty),
+                });
+            }
+            values.push(value_to_json(value, name)?);
+        }
+        rows.push(values);
+    }
+
+    let columns = names
+        .into_iter()
+        .enumerate()
+        .map(|(index, name)| ColumnInfo {
+            name,
+            type_: observed[index]
+                .or(declared[index])
+                .unwrap_or(ColumnType::Json),
+        })
+        .collect();
+    Ok(QueryResult { columns, rows })
+}
+
+#[async_trait]
+impl SqlBackend for SqliteProx {
+    async fn query_json_maps(&self, query: SqlQuery) -> Result<Vec<JsonValue>, anyhow::Error> {
+        let omit_nulls = query.omit_nulls;
+        let QueryResult { columns, rows } = self.query_rows(query).await?;
+        let maps = rows
+            .into_iter()
+            .map(|row| {
+                let map = columns
+                    .iter()
+                    .zip(row)
+                    .filter(|(_, value)| !(omit_nulls && value.is_null()))
+                    .map(|(col, value)| (col.name.clone(), value))
+                    .collect();
+                JsonValue::Object(map)
+            })
+            .collect();
+        Ok(maps)
+    }
+
+    async fn query_column_arrays(
+        &self,
+        query: SqlQuery,
+    ) -> Result<(ColumnNames, Vec<Vec<JsonValue>>), anyhow::Error> {
+        let QueryResult { columns, rows } = self.query_rows(query).await?;
+
+        // Match the Postgres backend, which only knows the names if there
+        // are rows.
+        let names = if rows.is_empty() {
+            vec![]
+        } else {
+            columns.into_iter().map(|c| c.name).collect()
+        };
+        Ok((names, rows))
+    }
+
+    async fn query_typed_columns(
+        &self,
+        query: SqlQuery,
+    ) -> Result<(Vec<ColumnInfo>, Vec<Vec<JsonValue>>), anyhow::Error> {
+        let QueryResult { columns, rows } = self.query_rows(query).await?;
+        Ok((columns, rows))
+    }
+}
+
+/// Convert a JSON argument to a SQLite value.
+///
+/// Nested arrays and objects are bound as JSON text.
+fn json_to_value(value: &JsonValue) -> Value {
+    match value {
+        JsonValue::Null => Value::Null,
+        JsonValue::Bool(v) => Value::Integer(*v as i64),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(v) => Value::Integer(v),
+            None => Value::Real(n.as_f64().unwrap_or_default()),
+        },
+        JsonValue::String(s) => Value::Text(s.clone()),
+        JsonValue::Array(_) | JsonValue::Object(_) => Value::Text(value.to_string()),
+    }
+}
+
+fn value_to_json(value: ValueRef<'_>, column: &str) -> Result<JsonValue, anyhow::Error> {
+    let json = match value {
+        ValueRef::Null => JsonValue::Null,
+        ValueRef::Integer(v) => JsonValue::from(v),
+        ValueRef::Real(v) => serde_json::Number::from_f64(v)
+            .map(JsonValue::Number)
+            .unwrap_or(JsonValue::Null),
+        ValueRef::Text(bytes) => match std::str::from_utf8(bytes) {
+            Ok(s) => JsonValue::String(s.to_string()),
+            Err(_) => bail!("Could not convert column '{column}' to json - invalid UTF-8"),
+        },
+        ValueRef::Blob(bytes) => {
+            JsonValue::String(base64::engine::general_purpose::STANDARD.encode(bytes))
+        }
+    };
+    Ok(json)
+}
+
+/// The column type of a single value.
+///
+/// Must stay in sync with the values produced by [`value_to_json`].
+fn value_type(value: ValueRef<'_>) -> Option<ColumnType> {
+    match value {
+        ValueRef::Null => None,
+        ValueRef::Integer(_) => Some(ColumnType::Int64),
+        ValueRef::Real(_) => Some(ColumnType::Float64),
+        ValueRef::Text(_) | ValueRef::Blob(_) => Some(ColumnType::Text),
+    }
+}
+
+/// Combine the types of two values in the same column.
+fn merge_types(a: ColumnType, b: ColumnType) -> ColumnType {
+    match (a, b) {
+        _ if a == b => a,
+        (ColumnType::Int64, ColumnType::Float64) | (ColumnType::Float64, ColumnType::Int64) => {
+            ColumnType::Float64
+        }
+        _ => ColumnType::Json,
+    }
+}
+
+/// Column type from the declared type of a table column, following the
+/// SQLite type affinity rules.
+///
+/// Only used if a column has no non-NULL values.
+fn declared_type(decl: &str) -> Option<ColumnType> {
+    let decl = decl.to_ascii_uppercase();
+    if decl.contains("INT") {
+        Some(ColumnType::Int64)
+    } else if decl.contains("CHAR") || decl.contains("CLOB") || decl.contains("TEXT") {
+        Some(ColumnType::Text)
+    } else if decl.contains("REAL") || decl.contains("FLOA") || decl.contains("DOUB") {
+        Some(ColumnType::Float64)
+    } else if decl.contains("BLOB") {
+        Some(ColumnType::Text)
+    } else {
+        None
+    }
+}